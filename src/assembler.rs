@@ -0,0 +1,774 @@
+use crate::memory::MEMORY_SIZE;
+use crate::opcode::Opcode;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where programs are loaded, and so the address of the first assembled byte
+pub const ORIGIN: u16 = 0x200;
+
+// Guards against include cycles and constants defined in terms of themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+// Names that are operands, so they can't be used for labels or constants
+const RESERVED: [&str; 10] = ["i", "dt", "st", "k", "f", "hf", "b", "r", "long", "[i]"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+pub type Result<T> = std::result::Result<T, AssembleError>;
+
+fn error<T>(location: &Location, message: String) -> Result<T> {
+    Err(AssembleError {
+        location: location.clone(),
+        message,
+    })
+}
+
+/// Assembles the source, reading includes relative to the current directory. `name` is only
+/// used in error messages.
+pub fn assemble(source: &str, name: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::default();
+    assembler.parse(source, name, Path::new(""), 0)?;
+    assembler.finish()
+}
+
+/// Assembles a source file, reading includes relative to the file's directory
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).or_else(|e| {
+        let location = Location {
+            file: name.clone(),
+            line: 0,
+            column: 0,
+        };
+        error(&location, format!("Could not read file: {}", e))
+    })?;
+    let mut assembler = Assembler::default();
+    assembler.parse(&source, &name, path.parent().unwrap_or(Path::new("")), 0)?;
+    assembler.finish()
+}
+
+// Operand text along with where it starts
+#[derive(Clone, Debug)]
+struct Operand {
+    text: String,
+    location: Location,
+}
+
+enum Directive {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Operand>),
+    Words(Vec<Operand>),
+}
+
+struct Statement {
+    location: Location,
+    directive: Directive,
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(Operand),
+}
+
+#[derive(Default)]
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, (Symbol, Location)>,
+    // Bytes assembled so far, which gives the address of the next statement
+    size: usize,
+}
+
+impl Assembler {
+    // First pass: splits the source into statements and records where each label points
+    fn parse(&mut self, source: &str, name: &str, directory: &Path, depth: usize) -> Result<()> {
+        for (index, line) in source.lines().enumerate() {
+            let location = |column: usize| Location {
+                file: name.to_string(),
+                line: index + 1,
+                column: column + 1,
+            };
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let mut start = line.len() - line.trim_start().len();
+            let mut rest = line.trim();
+
+            if let Some(colon) = rest.find(':') {
+                let label = &rest[..colon];
+                if is_identifier(label) {
+                    self.define(label, Symbol::Label(self.address()), location(start))?;
+                    let after = &rest[colon + 1..];
+                    start += colon + 1 + after.len() - after.trim_start().len();
+                    rest = after.trim();
+                }
+            }
+            if rest.is_empty() {
+                continue;
+            }
+
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..word_end];
+            let after = &rest[word_end..];
+            let after_start = start + word_end;
+            let after_trimmed = after.trim_start();
+            let equ = starts_with_keyword(after_trimmed, "equ");
+            if after_trimmed.starts_with('=') || equ {
+                if !is_identifier(word) {
+                    return error(&location(start), format!("'{}' is not a valid name", word));
+                }
+                let skip = if equ { 3 } else { 1 };
+                let value_start = after_start + after.len() - after_trimmed.len() + skip;
+                let value = split_operands(&after_trimmed[skip..], value_start, &location);
+                if value.len() != 1 || value[0].text.is_empty() {
+                    return error(&location(start), format!("'{}' needs one value", word));
+                }
+                let value = value.into_iter().next().unwrap();
+                self.define(word, Symbol::Constant(value), location(start))?;
+                continue;
+            }
+
+            let operands = split_operands(after, after_start, &location);
+            let directive = match &word.to_ascii_lowercase()[..] {
+                "include" => {
+                    self.include(&operands, &location(start), directory, depth)?;
+                    continue;
+                }
+                "db" => Directive::Bytes(operands),
+                "dw" => Directive::Words(operands),
+                mnemonic => Directive::Instruction {
+                    mnemonic: mnemonic.to_string(),
+                    operands,
+                },
+            };
+            let statement = Statement {
+                location: location(start),
+                directive,
+            };
+            self.size += statement_size(&statement);
+            if ORIGIN as usize + self.size > MEMORY_SIZE {
+                return error(
+                    &statement.location,
+                    "Program does not fit in memory".to_string(),
+                );
+            }
+            self.statements.push(statement);
+        }
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        operands: &[Operand],
+        location: &Location,
+        directory: &Path,
+        depth: usize,
+    ) -> Result<()> {
+        let path = match operands {
+            [operand]
+                if operand.text.len() >= 2
+                    && operand.text.starts_with('"')
+                    && operand.text.ends_with('"') =>
+            {
+                &operand.text[1..operand.text.len() - 1]
+            }
+            _ => return error(location, "include needs a quoted file name".to_string()),
+        };
+        if depth >= MAX_INCLUDE_DEPTH {
+            return error(location, "Includes are nested too deeply".to_string());
+        }
+        let path: PathBuf = directory.join(path);
+        let source = fs::read_to_string(&path).or_else(|e| {
+            error(
+                location,
+                format!("Could not include {}: {}", path.display(), e),
+            )
+        })?;
+        let name = path.display().to_string();
+        self.parse(
+            &source,
+            &name,
+            path.parent().unwrap_or(Path::new("")),
+            depth + 1,
+        )
+    }
+
+    fn address(&self) -> u16 {
+        (ORIGIN as usize + self.size) as u16
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: Location) -> Result<()> {
+        let key = name.to_ascii_lowercase();
+        if RESERVED.contains(&&key[..]) || parse_register(&key).is_some() {
+            return error(&location, format!("'{}' is a reserved name", name));
+        }
+        if let Some((_, previous)) = self.symbols.get(&key) {
+            return error(
+                &location,
+                format!("'{}' is already defined at {}", name, previous),
+            );
+        }
+        self.symbols.insert(key, (symbol, location));
+        Ok(())
+    }
+
+    // Second pass: now every label is known, turns the statements into bytes
+    fn finish(self) -> Result<Vec<u8>> {
+        let mut program = Vec::with_capacity(self.size);
+        for statement in &self.statements {
+            match &statement.directive {
+                Directive::Bytes(operands) => {
+                    for operand in operands {
+                        program.push(self.value(operand, 0xFF)? as u8);
+                    }
+                }
+                Directive::Words(operands) => {
+                    for operand in operands {
+                        let word = self.value(operand, 0xFFFF)? as u16;
+                        program.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                Directive::Instruction { mnemonic, operands } => {
+                    let (opcode, long) =
+                        self.instruction(mnemonic, operands, &statement.location)?;
+                    let word = u16::from(opcode);
+                    if Opcode::from(word) != opcode {
+                        return error(
+                            &statement.location,
+                            format!("'{}' can't be encoded", opcode),
+                        );
+                    }
+                    program.extend_from_slice(&word.to_be_bytes());
+                    if let Some(long) = long {
+                        program.extend_from_slice(&long.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    // The opcode for one instruction, plus the address word following a long load
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        location: &Location,
+    ) -> Result<(Opcode, Option<u16>)> {
+        let kinds = operands.iter().map(operand_kind).collect::<Vec<_>>();
+        // Point at the operands rather than the mnemonic where there are some
+        let wrong_operands = || {
+            error(
+                operands
+                    .first()
+                    .map_or(location, |operand| &operand.location),
+                format!("Invalid operands for {}", mnemonic.to_ascii_uppercase()),
+            )
+        };
+        let address = |index: usize| {
+            self.value(&operands[index], 0xFFF)
+                .map(|value| value as u16)
+        };
+        let byte = |index: usize| self.value(&operands[index], 0xFF).map(|value| value as u8);
+        let nibble = |index: usize| self.value(&operands[index], 0xF).map(|value| value as u8);
+
+        use Kind::*;
+        let opcode = match (mnemonic, &kinds[..]) {
+            ("cls", []) => Opcode::ClearScreen,
+            ("ret", []) => Opcode::Return,
+            ("scr", []) => Opcode::ScrollRight,
+            ("scl", []) => Opcode::ScrollLeft,
+            ("exit", []) => Opcode::Exit,
+            ("low", []) => Opcode::LowRes,
+            ("high", []) => Opcode::HighRes,
+            ("audio", []) => Opcode::LoadAudioPattern,
+            ("scd", [Value]) => Opcode::ScrollDown { lines: nibble(0)? },
+            ("scu", [Value]) => Opcode::ScrollUp { lines: nibble(0)? },
+            ("sys", [Value]) => Opcode::CallAddress {
+                address: address(0)?,
+            },
+            ("jp", [Value]) => Opcode::Goto {
+                address: address(0)?,
+            },
+            ("jp", [Register(0), Value]) => Opcode::JumpIndirect {
+                address: address(1)?,
+            },
+            ("call", [Value]) => Opcode::CallSubroutine {
+                address: address(0)?,
+            },
+            ("se", [Register(x), Value]) => Opcode::IfRegEqual {
+                register: *x,
+                immediate: byte(1)?,
+            },
+            ("se", [Register(x), Register(y)]) => Opcode::IfRegsEqual {
+                register1: *x,
+                register2: *y,
+            },
+            ("sne", [Register(x), Value]) => Opcode::IfRegNotEqual {
+                register: *x,
+                immediate: byte(1)?,
+            },
+            ("sne", [Register(x), Register(y)]) => Opcode::IfRegsNotEqual {
+                register1: *x,
+                register2: *y,
+            },
+            ("save", [RegisterRange(x, y)]) => Opcode::SaveRegisterRange {
+                register1: *x,
+                register2: *y,
+            },
+            ("load", [RegisterRange(x, y)]) => Opcode::LoadRegisterRange {
+                register1: *x,
+                register2: *y,
+            },
+            ("ld", [Register(x), Value]) => Opcode::SetRegister {
+                register: *x,
+                immediate: byte(1)?,
+            },
+            ("ld", [Register(x), Register(y)]) => Opcode::MoveRegToReg {
+                register1: *x,
+                register2: *y,
+            },
+            ("ld", [Name("i"), Value]) => Opcode::SetIToAddress {
+                address: address(1)?,
+            },
+            ("ld", [Name("i"), Long]) => {
+                let value = long_operand(&operands[1]);
+                let long = self.value(&value, 0xFFFF)? as u16;
+                return Ok((Opcode::SetILong, Some(long)));
+            }
+            ("ld", [Register(x), Name("dt")]) => Opcode::GetDelay { register: *x },
+            ("ld", [Register(x), Name("k")]) => Opcode::GetKey { register: *x },
+            ("ld", [Name("dt"), Register(x)]) => Opcode::SetDelay { register: *x },
+            ("ld", [Name("st"), Register(x)]) => Opcode::SetSound { register: *x },
+            ("ld", [Name("f"), Register(x)]) => Opcode::GetSpriteAddr { register: *x },
+            ("ld", [Name("hf"), Register(x)]) => Opcode::GetBigSpriteAddr { register: *x },
+            ("ld", [Name("b"), Register(x)]) => Opcode::ToBinaryCodedDecimal { register: *x },
+            ("ld", [Name("[i]"), Register(x)]) => Opcode::DumpRegistersUntil { register: *x },
+            ("ld", [Register(x), Name("[i]")]) => Opcode::LoadRegistersUntil { register: *x },
+            ("ld", [Name("r"), Register(x)]) => Opcode::SaveFlagsUntil { register: *x },
+            ("ld", [Register(x), Name("r")]) => Opcode::LoadFlagsUntil { register: *x },
+            ("add", [Register(x), Value]) => Opcode::AddToRegister {
+                register: *x,
+                immediate: byte(1)?,
+            },
+            ("add", [Register(x), Register(y)]) => Opcode::AddRegs {
+                register1: *x,
+                register2: *y,
+            },
+            ("add", [Name("i"), Register(x)]) => Opcode::AddRegToI { register: *x },
+            ("or", [Register(x), Register(y)]) => Opcode::BitwiseOrRegs {
+                register1: *x,
+                register2: *y,
+            },
+            ("and", [Register(x), Register(y)]) => Opcode::BitwiseAndRegs {
+                register1: *x,
+                register2: *y,
+            },
+            ("xor", [Register(x), Register(y)]) => Opcode::BitwiseXorRegs {
+                register1: *x,
+                register2: *y,
+            },
+            ("sub", [Register(x), Register(y)]) => Opcode::SubtractRegs {
+                register1: *x,
+                register2: *y,
+            },
+            ("subn", [Register(x), Register(y)]) => Opcode::SubtractRegsOppositeOrder {
+                register1: *x,
+                register2: *y,
+            },
+            // Shifting a register by itself behaves the same whichever register the quirks use
+            ("shr", [Register(x)]) => Opcode::RightShiftReg {
+                register1: *x,
+                register2: *x,
+            },
+            ("shr", [Register(x), Register(y)]) => Opcode::RightShiftReg {
+                register1: *x,
+                register2: *y,
+            },
+            ("shl", [Register(x)]) => Opcode::LeftShiftReg {
+                register1: *x,
+                register2: *x,
+            },
+            ("shl", [Register(x), Register(y)]) => Opcode::LeftShiftReg {
+                register1: *x,
+                register2: *y,
+            },
+            ("rnd", [Register(x), Value]) => Opcode::Rand {
+                register: *x,
+                immediate: byte(1)?,
+            },
+            ("drw", [Register(x), Register(y), Value]) => Opcode::Draw {
+                register1: *x,
+                register2: *y,
+                height: nibble(2)?,
+            },
+            ("skp", [Register(x)]) => Opcode::IfKeyEqual { register: *x },
+            ("sknp", [Register(x)]) => Opcode::IfKeyNotEqual { register: *x },
+            ("plane", [Value]) => Opcode::SelectPlanes { planes: nibble(0)? },
+            ("pitch", [Register(x)]) => Opcode::SetPitch { register: *x },
+            _ if MNEMONICS.contains(&mnemonic) => return wrong_operands(),
+            _ => {
+                return error(
+                    location,
+                    format!("Unknown instruction '{}'", mnemonic.to_ascii_uppercase()),
+                )
+            }
+        };
+        Ok((opcode, None))
+    }
+
+    // Evaluates an operand and checks that it fits in 0..=max
+    fn value(&self, operand: &Operand, max: u32) -> Result<u32> {
+        let value = self.evaluate(operand, 0)?;
+        if !(0..=max as i64).contains(&value) {
+            return error(
+                &operand.location,
+                format!(
+                    "{} is out of range, it should be 0 to {:#X}",
+                    operand.text, max
+                ),
+            );
+        }
+        Ok(value as u32)
+    }
+
+    // Sums up terms like `label + 2 - offset`
+    fn evaluate(&self, operand: &Operand, depth: usize) -> Result<i64> {
+        let text = &operand.text;
+        if text.is_empty() {
+            return error(&operand.location, "Missing value".to_string());
+        }
+        let mut total = 0i64;
+        let mut negative = false;
+        let mut term_start = 0;
+        for (index, c) in text
+            .char_indices()
+            .chain(std::iter::once((text.len(), '+')))
+        {
+            if c != '+' && c != '-' {
+                continue;
+            }
+            let term = text[term_start..index].trim();
+            if term.is_empty() {
+                // A sign with nothing before it, like `-1` or `2 - -1`
+                if index == text.len() {
+                    return error(&operand.location, format!("'{}' is not a value", text));
+                }
+                negative ^= c == '-';
+            } else {
+                let value = self.term(term, operand, depth)?;
+                total = if negative {
+                    value.checked_neg()
+                } else {
+                    Some(value)
+                }
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(|| AssembleError {
+                    location: operand.location.clone(),
+                    message: format!("{} is out of range", text),
+                })?;
+                negative = c == '-';
+            }
+            term_start = index + 1;
+        }
+        Ok(total)
+    }
+
+    fn term(&self, term: &str, operand: &Operand, depth: usize) -> Result<i64> {
+        if term.starts_with(|c: char| c.is_ascii_digit()) {
+            return match parse_number(term) {
+                Some(value) => Ok(value),
+                None => error(&operand.location, format!("'{}' is not a number", term)),
+            };
+        }
+        match self.symbols.get(&term.to_ascii_lowercase()) {
+            Some((Symbol::Label(address), _)) => Ok(*address as i64),
+            Some((Symbol::Constant(value), _)) => {
+                if depth >= MAX_CONSTANT_DEPTH {
+                    return error(
+                        &operand.location,
+                        format!("'{}' is defined in terms of itself", term),
+                    );
+                }
+                self.evaluate(value, depth + 1)
+            }
+            None => error(&operand.location, format!("Undefined name '{}'", term)),
+        }
+    }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "cls", "ret", "scr", "scl", "exit", "low", "high", "audio", "scd", "scu", "sys", "jp", "call",
+    "se", "sne", "save", "load", "ld", "add", "or", "and", "xor", "sub", "subn", "shr", "shl",
+    "rnd", "drw", "skp", "sknp", "plane", "pitch",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Register(u8),
+    RegisterRange(u8, u8),
+    // One of the reserved operand names like I or DT, lowercased
+    Name(&'static str),
+    Long,
+    Value,
+}
+
+fn operand_kind(operand: &Operand) -> Kind {
+    let text = operand.text.to_ascii_lowercase();
+    if let Some(register) = parse_register(&text) {
+        return Kind::Register(register);
+    }
+    if let Some(&name) = RESERVED.iter().find(|&&name| name == text) {
+        return Kind::Name(name);
+    }
+    if is_long(&text) {
+        return Kind::Long;
+    }
+    let mut range = text.splitn(2, '-');
+    let first = range.next().and_then(|first| parse_register(first.trim()));
+    let second = range
+        .next()
+        .and_then(|second| parse_register(second.trim()));
+    if let (Some(first), Some(second)) = (first, second) {
+        return Kind::RegisterRange(first, second);
+    }
+    Kind::Value
+}
+
+// Whether `text` starts with `keyword`, in any case, and then whitespace
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    match (text.get(..keyword.len()), text.get(keyword.len()..)) {
+        (Some(start), Some(rest)) => {
+            start.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace)
+        }
+        _ => false,
+    }
+}
+
+fn is_long(text: &str) -> bool {
+    text.len() > 5 && starts_with_keyword(text, "long")
+}
+
+// The address part of `LONG address`
+fn long_operand(operand: &Operand) -> Operand {
+    let rest = operand.text.get(4..).unwrap_or("");
+    let trimmed = rest.trim_start();
+    let mut location = operand.location.clone();
+    location.column += 4 + rest.len() - trimmed.len();
+    Operand {
+        text: trimmed.to_string(),
+        location,
+    }
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let register = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if register.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(register, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// Splits on commas, keeping the column where each trimmed operand starts
+fn split_operands(text: &str, start: usize, location: &dyn Fn(usize) -> Location) -> Vec<Operand> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut offset = 0;
+    for part in text.split(',') {
+        let leading = part.len() - part.trim_start().len();
+        operands.push(Operand {
+            text: part.trim().to_string(),
+            location: location(start + offset + leading),
+        });
+        offset += part.len() + 1;
+    }
+    operands
+}
+
+fn statement_size(statement: &Statement) -> usize {
+    match &statement.directive {
+        Directive::Bytes(operands) => operands.len(),
+        Directive::Words(operands) => operands.len() * 2,
+        Directive::Instruction { mnemonic, operands } => {
+            let long = mnemonic == "ld" && operands.len() == 2 && is_long(&operands[1].text);
+            if long {
+                4
+            } else {
+                2
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
+
+    #[test]
+    fn every_disassembled_instruction_assembles_back() {
+        for word in 0..=0xFFFF_u16 {
+            let bytes = word.to_be_bytes();
+            let line = &disassembler::disassemble(&bytes, ORIGIN)[0];
+            if line.text.starts_with("DB") {
+                continue;
+            }
+            assert_eq!(assemble(&line.text, "test"), Ok(bytes.to_vec()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn programs_round_trip_through_the_disassembler() {
+        let source = "
+            SPEED = 3
+            start:
+                CLS
+                LD V0, SPEED + 1
+                LD I, LONG sprite
+                CALL draw
+            loop:
+                SE V0, 0x10
+                JP loop
+                EXIT
+            draw:
+                DRW V0, V1, 5
+                RET
+            sprite:
+                DB 0xF0, 0x90, 0xF0
+                DW 0x1234
+        ";
+        let program = assemble(source, "test").unwrap();
+        let disassembly = disassembler::disassemble_code(&program, ORIGIN)
+            .iter()
+            .map(|line| line.text.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&disassembly, "disassembly"), Ok(program));
+    }
+
+    fn error_at(source: &str) -> (usize, usize, String) {
+        let error = assemble(source, "test").unwrap_err();
+        assert_eq!(error.location.file, "test");
+        (error.location.line, error.location.column, error.message)
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        assert_eq!(
+            error_at("CLS\n  JUMP 0x200"),
+            (2, 3, "Unknown instruction 'JUMP'".to_string())
+        );
+        assert_eq!(
+            error_at("start:\n\n    JP nowhere"),
+            (3, 8, "Undefined name 'nowhere'".to_string())
+        );
+        assert_eq!(
+            error_at("LD V0, 1\nLD V1, 0x100 ; too big"),
+            (
+                2,
+                8,
+                "0x100 is out of range, it should be 0 to 0xFF".to_string()
+            )
+        );
+        assert_eq!(
+            error_at("DRW V0, V1, 16"),
+            (
+                1,
+                13,
+                "16 is out of range, it should be 0 to 0xF".to_string()
+            )
+        );
+        assert_eq!(
+            error_at("RET\nJP 0x1000"),
+            (
+                2,
+                4,
+                "0x1000 is out of range, it should be 0 to 0xFFF".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn non_ascii_text_is_an_error_not_a_panic() {
+        assert_eq!(
+            error_at("x equé 3"),
+            (1, 1, "Unknown instruction 'X'".to_string())
+        );
+        assert_eq!(
+            error_at("LD I, lon日 1"),
+            (1, 7, "Undefined name 'lon日 1'".to_string())
+        );
+        assert_eq!(
+            error_at("x aé 3"),
+            (1, 1, "Unknown instruction 'X'".to_string())
+        );
+    }
+
+    #[test]
+    fn overflowing_sums_are_errors() {
+        let big = "0x7FFFFFFFFFFFFFFF";
+        assert_eq!(
+            error_at(&format!("CLS\nLD V0, {} + {}", big, big)),
+            (2, 8, format!("{} + {} is out of range", big, big))
+        );
+        assert_eq!(
+            error_at(&format!("LD V0, 0 - {} - 2", big)),
+            (1, 8, format!("0 - {} - 2 is out of range", big))
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use crate::cpu::Cpu;
+use crate::frame;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+/// The sample rates `AudioRenderer` and `encode_wav` are meant for, in Hz
+pub const SAMPLE_RATES: RangeInclusive<u32> = 8000..=192_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 2] = [Waveform::Square, Waveform::Sine];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownWaveformError(pub String);
+
+impl fmt::Display for UnknownWaveformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown waveform '{}'", self.0)
+    }
+}
+
+impl Error for UnknownWaveformError {}
+
+impl FromStr for Waveform {
+    type Err = UnknownWaveformError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Waveform::ALL
+            .iter()
+            .copied()
+            .find(|waveform| waveform.name() == name)
+            .ok_or_else(|| UnknownWaveformError(name.to_string()))
+    }
+}
+
+/// What the buzzer sounds like
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    /// In Hz
+    pub frequency: f32,
+    /// From 0 for silence to 1 for full scale
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+}
+
+/// Produces samples of a tone while the sound timer is running and silence otherwise. The
+/// phase carries on between buffers so the tone doesn't click.
+pub struct ToneGenerator {
+    tone: Tone,
+    sample_rate: u32,
+    // Position in the current period, from 0 to 1
+    phase: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        ToneGenerator {
+            tone,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    /// Fills `samples` with the tone if `active` is set, or with silence
+    pub fn fill(&mut self, samples: &mut [f32], active: bool) {
+        if !active {
+            for sample in samples.iter_mut() {
+                *sample = 0.0;
+            }
+            return;
+        }
+        let step = self.tone.frequency / self.sample_rate as f32;
+        for sample in samples.iter_mut() {
+            let level = match self.tone.waveform {
+                Waveform::Square => {
+                    if self.phase < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            };
+            *sample = level * self.tone.volume;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// Turns what the CPU's sound hardware is doing into samples, one frame at a time. Plays
+/// the XO-CHIP audio pattern at its pitch if the program has loaded one, or the tone
+/// otherwise.
+pub struct AudioRenderer {
+    generator: ToneGenerator,
+    sample_rate: u32,
+    // Position in the 128 bit pattern
+    pattern_position: f32,
+    // Samples owed from frames that didn't divide the sample rate evenly
+    remainder: u32,
+}
+
+impl AudioRenderer {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        AudioRenderer {
+            generator: ToneGenerator::new(tone, sample_rate),
+            sample_rate,
+            pattern_position: 0.0,
+            remainder: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Appends a frame's worth of samples for the sound as the CPU has it now
+    pub fn render_frame(&mut self, cpu: &Cpu, samples: &mut Vec<f32>) {
+        let total = self.sample_rate + self.remainder;
+        let count = (total / frame::FRAME_RATE) as usize;
+        self.remainder = total % frame::FRAME_RATE;
+        let start = samples.len();
+        samples.resize(start + count, 0.0);
+        let buffer = &mut samples[start..];
+
+        let pattern = match cpu.audio_pattern() {
+            Some(pattern) => pattern,
+            None => {
+                self.generator.fill(buffer, cpu.is_sound_active());
+                return;
+            }
+        };
+        if !cpu.is_sound_active() {
+            return;
+        }
+        let step = cpu.audio_playback_rate() / self.sample_rate as f32;
+        let volume = self.generator.tone().volume;
+        for sample in buffer.iter_mut() {
+            let bit = self.pattern_position as usize;
+            let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if set { volume } else { -volume };
+            self.pattern_position = (self.pattern_position + step) % 128.0;
+        }
+    }
+}
+
+/// A mono 16-bit PCM WAV file of samples from -1 to 1. Fails if there are too many samples
+/// for the 32-bit sizes in the header.
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, String> {
+    const BYTES_PER_SAMPLE: u32 = 2;
+    let too_long = || format!("{} samples are too many for a WAV file", samples.len());
+    let data_size = u32::try_from(samples.len())
+        .ok()
+        .and_then(|len| len.checked_mul(BYTES_PER_SAMPLE))
+        .filter(|size| size.checked_add(36).is_some())
+        .ok_or_else(too_long)?;
+    let byte_rate = sample_rate
+        .checked_mul(BYTES_PER_SAMPLE)
+        .ok_or_else(|| format!("{}Hz is too high a sample rate", sample_rate))?;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&(BYTES_PER_SAMPLE as u16).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    Ok(wav)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(wav: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            wav[offset],
+            wav[offset + 1],
+            wav[offset + 2],
+            wav[offset + 3],
+        ])
+    }
+
+    fn u16_at(wav: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([wav[offset], wav[offset + 1]])
+    }
+
+    #[test]
+    fn wav_header_fields() {
+        let wav = encode_wav(&[0.0, 1.0, -1.0], 22050).unwrap();
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), 36 + 6);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 16), 16);
+        // PCM, mono, the rate, bytes per second, bytes per sample and bits per sample
+        assert_eq!(u16_at(&wav, 20), 1);
+        assert_eq!(u16_at(&wav, 22), 1);
+        assert_eq!(u32_at(&wav, 24), 22050);
+        assert_eq!(u32_at(&wav, 28), 44100);
+        assert_eq!(u16_at(&wav, 32), 2);
+        assert_eq!(u16_at(&wav, 34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 6);
+        let samples = [u16_at(&wav, 44), u16_at(&wav, 46), u16_at(&wav, 48)];
+        assert_eq!(samples, [0, i16::MAX as u16, -i16::MAX as u16]);
+    }
+
+    #[test]
+    fn wav_rejects_rates_the_header_cannot_hold() {
+        assert!(encode_wav(&[], u32::MAX).is_err());
+    }
+
+    // Counts the times the samples go from negative to positive
+    fn rising_edges(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] > 0.0)
+            .count()
+    }
+
+    #[test]
+    fn the_tone_plays_while_the_sound_timer_runs() {
+        let mut cpu = Cpu::new();
+        let mut renderer = AudioRenderer::new(Tone::default(), 48000);
+        let mut samples = Vec::new();
+        renderer.render_frame(&cpu, &mut samples);
+        assert_eq!(samples.len(), 800);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+
+        cpu.set_sound_timer(60);
+        samples.clear();
+        for _ in 0..60 {
+            renderer.render_frame(&cpu, &mut samples);
+        }
+        assert_eq!(samples.len(), 48000);
+        // A second of 440Hz
+        assert!((439..=441).contains(&rising_edges(&samples)));
+        assert!(samples.iter().all(|sample| sample.abs() == DEFAULT_VOLUME));
+    }
+
+    #[test]
+    fn frames_share_out_uneven_sample_rates() {
+        let cpu = Cpu::new();
+        let mut renderer = AudioRenderer::new(Tone::default(), 44100);
+        let mut samples = Vec::new();
+        for _ in 0..60 {
+            renderer.render_frame(&cpu, &mut samples);
+        }
+        assert_eq!(samples.len(), 44100);
+    }
+
+    #[test]
+    fn a_loaded_pattern_plays_instead_of_the_tone() {
+        let mut cpu = Cpu::new();
+        // LD I, 0x300 then F002 to load the pattern there
+        let program = [0xA3, 0x00, 0xF0, 0x02];
+        cpu.memory_mut().write_data(0x200, &program).unwrap();
+        let mut pattern = [0; 16];
+        pattern[0] = 0xF0;
+        cpu.memory_mut().write_data(0x300, &pattern).unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.audio_pattern(), Some(&pattern));
+        cpu.set_sound_timer(1);
+
+        // At the default pitch the pattern plays at 4000Hz, two samples per bit at 8000Hz
+        let mut renderer = AudioRenderer::new(Tone::default(), 8000);
+        let mut samples = Vec::new();
+        renderer.render_frame(&cpu, &mut samples);
+        assert_eq!(samples.len(), 133);
+        for (index, &sample) in samples.iter().enumerate() {
+            let bit = index / 2 % 128;
+            let expected = if bit < 4 {
+                DEFAULT_VOLUME
+            } else {
+                -DEFAULT_VOLUME
+            };
+            assert_eq!(sample, expected, "sample {}", index);
+        }
+    }
+
+    #[test]
+    fn a_silent_pattern_stays_silent() {
+        let mut cpu = Cpu::new();
+        cpu.memory_mut()
+            .write_data(0x200, &[0xA3, 0x00, 0xF0, 0x02])
+            .unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.set_sound_timer(1);
+        let mut renderer = AudioRenderer::new(Tone::default(), 48000);
+        let mut samples = Vec::new();
+        renderer.render_frame(&cpu, &mut samples);
+        assert_eq!(rising_edges(&samples), 0);
+    }
+}
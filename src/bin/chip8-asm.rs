@@ -0,0 +1,68 @@
+use chip8_emu::assembler;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] <source to assemble>", name);
+    println!();
+    println!("Options:");
+    println!("  -o, --output <file>   Where to write the ROM (default: the source with a .ch8");
+    println!("                        extension)");
+}
+
+struct Options {
+    source: PathBuf,
+    output: PathBuf,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-o" | "--output" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                output = Some(PathBuf::from(value));
+            }
+            _ if source.is_none() && !arg.starts_with('-') => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let source = source.ok_or("no source given")?;
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+    Ok(Options { source, output })
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let name = if args.is_empty() {
+        "chip8-asm"
+    } else {
+        &args[0][..]
+    };
+    let options = match parse_args(args.get(1..).unwrap_or(&[])) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage(name);
+            process::exit(2);
+        }
+    };
+
+    let rom = match assembler::assemble_file(&options.source) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = fs::write(&options.output, &rom) {
+        eprintln!("Could not write {}: {}", options.output.display(), e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,67 @@
+use chip8_emu::disassembler;
+
+use std::fs;
+use std::process;
+
+// Where programs are loaded and start executing
+const PROGRAM_START: u16 = 0x200;
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] <rom to disassemble>", name);
+    println!();
+    println!("Options:");
+    println!("  --linear              Decode every word as an instruction instead of");
+    println!("                        following control flow from the start");
+}
+
+struct Options {
+    rom: String,
+    linear: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut linear = false;
+    for arg in args {
+        match &arg[..] {
+            "--linear" => linear = true,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom = rom.ok_or("no rom given")?;
+    Ok(Options { rom, linear })
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let name = if args.is_empty() {
+        "chip8-disasm"
+    } else {
+        &args[0][..]
+    };
+    let options = match parse_args(args.get(1..).unwrap_or(&[])) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage(name);
+            process::exit(2);
+        }
+    };
+
+    let rom = match fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", options.rom, e);
+            process::exit(1);
+        }
+    };
+    let lines = if options.linear {
+        disassembler::disassemble(&rom, PROGRAM_START)
+    } else {
+        disassembler::disassemble_code(&rom, PROGRAM_START)
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+}
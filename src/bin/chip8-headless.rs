@@ -0,0 +1,398 @@
+use chip8_emu::audio::{self, AudioRenderer, Tone};
+use chip8_emu::cpu::Cpu;
+use chip8_emu::gif::GifRecorder;
+use chip8_emu::headless::{self, KeyEvent, RunLength, Runner};
+use chip8_emu::image::{Image, ImageFormat};
+use chip8_emu::movie::{self, Movie};
+use chip8_emu::palette::Palette;
+use chip8_emu::quirks::{Preset, Quirks};
+use chip8_emu::trace::{self, Tracer};
+use chip8_emu::util::{parse_count, parse_number};
+
+use std::convert::TryInto;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process;
+
+const MAX_SCALE: u32 = 64;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] <rom to load>", name);
+    println!();
+    println!("Options:");
+    println!("  --cycles <n>          Run for n instructions");
+    println!("  --frames <n>          Run for n frames (default 60)");
+    println!(
+        "  --ipf <n>             Instructions per frame (default {})",
+        headless::DEFAULT_CYCLES_PER_FRAME
+    );
+    println!("  --quirks <preset>     Quirks preset to emulate");
+    println!("  --seed <n>            Seed the random number generator (default 0)");
+    println!("  --press <step>:<key>  Press hex key at a frame (or cycle with --cycles)");
+    println!("  --release <step>:<key>");
+    println!("                        Release hex key at a frame (or cycle with --cycles)");
+    println!("  --format <ascii|pbm>  How to dump the framebuffer (default ascii)");
+    println!("  --screenshot-at-frame <n>");
+    println!("                        Save the screen as it is after frame n");
+    println!("  --screenshot <file>   Where to save it, .png or .ppm (default <rom>.frame<n>.png)");
+    println!("  --gif <file>          Record the run as an animated GIF");
+    println!("  --gif-frames <start>-<end>");
+    println!("                        Only record these frames, counting from 1");
+    println!("  --wav <file>          Record the sound to a WAV file");
+    println!(
+        "  --sample-rate <hz>    Samples per second in the WAV file (default {})",
+        DEFAULT_SAMPLE_RATE
+    );
+    println!("  --scale <n>           Image pixels per CHIP-8 pixel (default 1)");
+    println!("  --palette <palette>   Preset name, or 2 or 4 RRGGBB colours separated by commas");
+    println!("  --record <file>       Record the key presses to a movie file");
+    println!("  --play <file>         Play a movie back, checking for desyncs, instead of");
+    println!("                        running for --frames with --press and --release");
+    println!("  --trace <file>        Log every executed instruction to file");
+    println!("  --trace-addr <start>-<end>");
+    println!("                        Only log instructions in this address range");
+    println!("  --trace-cycles <start>-<end>");
+    println!("                        Only log instructions in this cycle range");
+    let presets = Preset::ALL
+        .iter()
+        .map(|preset| preset.name())
+        .collect::<Vec<_>>();
+    println!();
+    println!("Quirks presets: {}", presets.join(", "));
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Ascii,
+    Pbm,
+}
+
+struct Options {
+    rom: String,
+    runner: Runner,
+    quirks: Quirks,
+    seed: u64,
+    format: Format,
+    screenshot_frame: Option<u64>,
+    screenshot: Option<PathBuf>,
+    gif: Option<PathBuf>,
+    gif_frames: Option<RangeInclusive<u64>>,
+    wav: Option<PathBuf>,
+    sample_rate: u32,
+    scale: u32,
+    palette: Palette,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+    trace: Option<String>,
+    trace_addresses: Option<RangeInclusive<u16>>,
+    trace_cycles: Option<RangeInclusive<u64>>,
+}
+
+fn parse_key_event(value: &str, pressed: bool) -> Result<KeyEvent, String> {
+    let mut parts = value.splitn(2, ':');
+    let step = parse_number(parts.next().unwrap())?;
+    let key = parts
+        .next()
+        .and_then(|key| u8::from_str_radix(key, 16).ok())
+        .filter(|&key| key < 16)
+        .ok_or_else(|| format!("'{}' should be <step>:<hex key>", value))?;
+    Ok(KeyEvent { step, key, pressed })
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut runner = Runner::new(RunLength::Frames(60));
+    let mut quirks = Quirks::default();
+    // Fixed rather than random so the same run always gives the same output
+    let mut seed = 0;
+    let mut format = Format::Ascii;
+    let mut screenshot_frame = None;
+    let mut screenshot = None;
+    let mut gif = None;
+    let mut gif_frames = None;
+    let mut wav = None;
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut scale = 1;
+    let mut palette = Palette::default();
+    let mut record = None;
+    let mut play = None;
+    let mut trace = None;
+    let mut trace_addresses = None;
+    let mut trace_cycles = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match &arg[..] {
+            "--cycles" => runner.length = RunLength::Cycles(parse_number(value()?)?),
+            "--frames" => runner.length = RunLength::Frames(parse_number(value()?)?),
+            // Movies store it as a u32
+            "--ipf" => runner.cycles_per_frame = parse_count(value()?, u32::MAX as u64)? as u64,
+            "--quirks" => {
+                quirks = value()?
+                    .parse::<Preset>()
+                    .map_err(|e| e.to_string())?
+                    .quirks();
+            }
+            "--seed" => seed = parse_number(value()?)?,
+            "--press" => runner.key_events.push(parse_key_event(value()?, true)?),
+            "--release" => runner.key_events.push(parse_key_event(value()?, false)?),
+            "--format" => {
+                format = match &value()?[..] {
+                    "ascii" => Format::Ascii,
+                    "pbm" => Format::Pbm,
+                    other => return Err(format!("unknown format '{}'", other)),
+                }
+            }
+            "--screenshot-at-frame" => screenshot_frame = Some(parse_number(value()?)?),
+            "--screenshot" => screenshot = Some(PathBuf::from(value()?)),
+            "--gif" => gif = Some(PathBuf::from(value()?)),
+            "--gif-frames" => gif_frames = Some(trace::parse_range(value()?)?),
+            "--wav" => wav = Some(PathBuf::from(value()?)),
+            "--sample-rate" => {
+                let value = value()?;
+                sample_rate = parse_number(value)?
+                    .try_into()
+                    .ok()
+                    .filter(|rate| audio::SAMPLE_RATES.contains(rate))
+                    .ok_or_else(|| {
+                        format!(
+                            "'{}' should be from {} to {}",
+                            value,
+                            audio::SAMPLE_RATES.start(),
+                            audio::SAMPLE_RATES.end()
+                        )
+                    })?;
+            }
+            "--scale" => {
+                let value = value()?;
+                scale = parse_number(value)?
+                    .try_into()
+                    .ok()
+                    .filter(|scale| (1..=MAX_SCALE).contains(scale))
+                    .ok_or_else(|| format!("'{}' should be from 1 to {}", value, MAX_SCALE))?;
+            }
+            "--palette" => palette = value()?.parse::<Palette>().map_err(|e| e.to_string())?,
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--play" => play = Some(PathBuf::from(value()?)),
+            "--trace" => trace = Some(value()?.clone()),
+            "--trace-addr" => trace_addresses = Some(trace::parse_address_range(value()?)?),
+            "--trace-cycles" => trace_cycles = Some(trace::parse_range(value()?)?),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom = rom.ok_or("no rom given")?;
+    let uses_movie = record.is_some() || play.is_some();
+    if uses_movie && matches!(runner.length, RunLength::Cycles(_)) {
+        return Err("movies count frames, so can't be used with --cycles".to_string());
+    }
+    let uses_frames = screenshot_frame.is_some() || gif.is_some() || wav.is_some();
+    if uses_frames && matches!(runner.length, RunLength::Cycles(_)) {
+        return Err("screenshots, GIFs and WAVs count frames, so need --frames".to_string());
+    }
+    if gif_frames.is_some() && gif.is_none() {
+        return Err("--gif-frames needs --gif".to_string());
+    }
+    let screenshot = match (screenshot_frame, screenshot) {
+        (Some(frame), None) => Some(PathBuf::from(format!("{}.frame{}.png", rom, frame))),
+        (_, Some(path)) if ImageFormat::from_path(&path).is_none() => {
+            return Err(format!("'{}' should end in .png or .ppm", path.display()))
+        }
+        (None, Some(_)) => return Err("--screenshot needs --screenshot-at-frame".to_string()),
+        (Some(_), path) => path,
+        (None, None) => None,
+    };
+    Ok(Options {
+        rom,
+        runner,
+        quirks,
+        seed,
+        format,
+        screenshot_frame,
+        screenshot,
+        gif,
+        gif_frames,
+        wav,
+        sample_rate,
+        scale,
+        palette,
+        record,
+        play,
+        trace,
+        trace_addresses,
+        trace_cycles,
+    })
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let name = if args.is_empty() {
+        "chip8-headless"
+    } else {
+        &args[0][..]
+    };
+    let mut options = match parse_args(args.get(1..).unwrap_or(&[])) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_usage(name);
+            process::exit(2);
+        }
+    };
+
+    let mut cpu = Cpu::with_quirks(options.quirks);
+    cpu.seed_rng(options.seed);
+    if let Err(e) = cpu.load_program(&options.rom) {
+        eprintln!("Could not load program {}: {}", options.rom, e);
+        process::exit(2);
+    }
+
+    let played = options.play.as_deref().map(|path| {
+        let movie = Movie::load(path).and_then(|movie| {
+            movie.start(&mut cpu)?;
+            Ok(movie)
+        });
+        movie.unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(2);
+        })
+    });
+    if let Some(movie) = &played {
+        options.runner.length = RunLength::Frames(movie.frames.len() as u64);
+        options.runner.cycles_per_frame = movie.instructions_per_frame as u64;
+        options.runner.key_events = movie.key_events();
+    }
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&cpu, options.runner.cycles_per_frame as u32));
+
+    if let Some(path) = &options.trace {
+        let mut tracer = match Tracer::create(path) {
+            Ok(tracer) => tracer,
+            Err(e) => {
+                eprintln!("Could not create trace file {}: {}", path, e);
+                process::exit(2);
+            }
+        };
+        tracer.addresses = options.trace_addresses.clone();
+        tracer.cycles = options.trace_cycles.clone();
+        cpu.set_tracer(Some(tracer));
+    }
+
+    let mut desyncs = 0;
+    let mut screenshot = None;
+    let mut gif = options
+        .gif
+        .as_ref()
+        .map(|_| GifRecorder::new(options.scale, options.palette));
+    let mut sound = options.wav.as_ref().map(|_| {
+        (
+            AudioRenderer::new(Tone::default(), options.sample_rate),
+            Vec::new(),
+        )
+    });
+    let result = options.runner.run_with(&mut cpu, |frame, cpu| {
+        if let Some((renderer, samples)) = sound.as_mut() {
+            renderer.render_frame(cpu, samples);
+        }
+        if let Some(gif) = gif.as_mut() {
+            let in_range = match &options.gif_frames {
+                Some(range) => range.contains(&(frame + 1)),
+                None => true,
+            };
+            if in_range {
+                gif.capture(cpu.screen());
+            }
+        }
+        if Some(frame + 1) == options.screenshot_frame {
+            screenshot = Some(Image::from_screen(
+                cpu.screen(),
+                options.scale,
+                &options.palette,
+            ));
+        }
+        if let Some(movie) = &played {
+            if let Err(desync) = movie.check_frame(frame as usize, cpu) {
+                eprintln!("{}", desync);
+                desyncs += 1;
+            }
+        }
+        if let Some(movie) = recording.as_mut() {
+            movie.record_frame(movie::keys(cpu), cpu);
+        }
+    });
+    // Output that couldn't be written fails the run, once the state has been dumped
+    let mut output_failed = false;
+    if let Some(path) = &options.screenshot {
+        match screenshot {
+            Some(image) => {
+                if let Err(e) = image.save(path) {
+                    eprintln!("Could not save screenshot {}: {}", path.display(), e);
+                    output_failed = true;
+                }
+            }
+            None => {
+                eprintln!("The run ended before the screenshot frame");
+                output_failed = true;
+            }
+        }
+    }
+    if let (Some((renderer, samples)), Some(path)) = (&sound, &options.wav) {
+        let saved = audio::encode_wav(samples, renderer.sample_rate())
+            .and_then(|wav| fs::write(path, wav).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            eprintln!("Could not save WAV {}: {}", path.display(), e);
+            output_failed = true;
+        }
+    }
+    if let (Some(gif), Some(path)) = (&gif, &options.gif) {
+        if let Err(e) = gif.save(path) {
+            eprintln!("Could not save GIF {}: {}", path.display(), e);
+            output_failed = true;
+        }
+    }
+    if let (Some(movie), Some(path)) = (&recording, &options.record) {
+        if let Err(e) = movie.save(path) {
+            eprintln!("{}", e);
+            output_failed = true;
+        }
+    }
+    if let Some(tracer) = cpu.take_tracer() {
+        if let Err(e) = tracer.finish() {
+            eprintln!("Could not write trace: {}", e);
+            output_failed = true;
+        }
+    }
+
+    // Dump the state even if the program crashed, it's the interesting case
+    let mut summary = String::new();
+    match result {
+        Ok(cycles) => summary.push_str(&format!("Cycles={}\n", cycles)),
+        Err(e) => summary.push_str(&format!("Error={}\n", e)),
+    }
+    summary.push_str(&format!("Seed={}\n", cpu.rng_seed()));
+    if played.is_some() {
+        summary.push_str(&format!("Desyncs={}\n", desyncs));
+    }
+    summary.push_str(&headless::register_dump(&cpu));
+    summary.push_str(&format!("Memory={:016x}\n", headless::memory_hash(&cpu)));
+
+    match options.format {
+        Format::Ascii => {
+            print!("{}", headless::framebuffer_ascii(cpu.screen()));
+            print!("{}", summary);
+        }
+        Format::Pbm => print!("{}", headless::framebuffer_pbm(cpu.screen(), &summary)),
+    }
+
+    if let Err(e) = result {
+        eprintln!("Emulation halted: {}", e);
+        process::exit(1);
+    }
+    if desyncs > 0 || output_failed {
+        process::exit(1);
+    }
+}
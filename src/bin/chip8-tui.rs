@@ -0,0 +1,359 @@
+use chip8_emu::config::{Config, Settings};
+use chip8_emu::cpu::Cpu;
+use chip8_emu::frame::{self, FrameLimiter, FrameOutcome};
+use chip8_emu::keymap::KeyMap;
+use chip8_emu::movie;
+use chip8_emu::palette::{Palette, Rgb};
+use chip8_emu::quirks::Preset;
+use chip8_emu::screen::Screen;
+use chip8_emu::util::{parse_count, parse_number};
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// Terminals only say when a key goes down, and then repeat it while held, so a key counts
+// as held until this many frames pass without it coming in again
+const DEFAULT_HOLD_FRAMES: u64 = 8;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_P: u8 = 0x10;
+const ESCAPE: u8 = 0x1B;
+
+// How long to wait after an escape for the rest of a sequence before taking it as the key
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] <rom to load>", name);
+    println!();
+    println!("Runs a ROM in the terminal, two pixels to a character.");
+    println!();
+    println!("Options:");
+    println!(
+        "  --ipf <n>             Instructions per frame (default {})",
+        frame::DEFAULT_INSTRUCTIONS_PER_FRAME
+    );
+    println!("  --clock <hz>          Instructions per second, rounded to whole frames");
+    println!("  --quirks <preset>     Quirks preset to emulate");
+    println!("  --palette <palette>   Preset name, or 2 or 4 RRGGBB colours separated by commas");
+    println!("  --keymap <preset|file>");
+    println!("                        Keyboard layout, or a file of `<hex key> = <key>` lines");
+    println!("  --seed <n>            Seed the random number generator");
+    println!(
+        "  --hold <frames>       How long a key stays down after the terminal sends it \
+         (default {})",
+        DEFAULT_HOLD_FRAMES
+    );
+    println!("  --no-bell             Don't ring the terminal bell for the sound timer");
+    println!("  --config <file>       Read settings from file instead of the default config");
+    println!("  --no-config           Don't read any config file");
+    println!("  -h, --help            Print this help");
+    println!();
+    println!("Escape or Ctrl+C quits and Ctrl+P pauses.");
+}
+
+struct Options {
+    rom: String,
+    settings: Settings,
+    config: Option<PathBuf>,
+    no_config: bool,
+    seed: Option<u64>,
+    hold_frames: u64,
+    bell: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut settings = Settings::default();
+    let mut config = None;
+    let mut no_config = false;
+    let mut seed = None;
+    let mut hold_frames = DEFAULT_HOLD_FRAMES;
+    let mut bell = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match &arg[..] {
+            "--ipf" => {
+                let max = (u32::MAX / frame::FRAME_RATE) as u64;
+                settings.clock = Some(parse_count(value()?, max)? * frame::FRAME_RATE);
+            }
+            "--clock" => settings.clock = Some(parse_count(value()?, u32::MAX as u64)?),
+            "--quirks" => {
+                let preset = value()?.parse::<Preset>().map_err(|e| e.to_string())?;
+                settings.quirks = Some(preset);
+            }
+            "--palette" => {
+                let palette = value()?.parse::<Palette>().map_err(|e| e.to_string())?;
+                settings.palette = Some(palette);
+            }
+            "--keymap" => settings.keymap = Some(PathBuf::from(value()?)),
+            "--seed" => seed = Some(parse_number(value()?)?),
+            "--hold" => hold_frames = parse_count(value()?, frame::FRAME_RATE as u64)? as u64,
+            "--no-bell" => bell = false,
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--no-config" => no_config = true,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom = rom.ok_or("no rom given")?;
+    Ok(Options {
+        rom,
+        settings,
+        config,
+        no_config,
+        seed,
+        hold_frames,
+        bell,
+    })
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Could not run stty: {}", e))?;
+    if !output.status.success() {
+        return Err("stdin is not a terminal".to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Puts the terminal in raw mode on an alternate screen, and puts it back when dropped, so it
+// is also restored when something goes wrong
+struct Terminal {
+    saved: String,
+}
+
+impl Terminal {
+    fn enter() -> Result<Terminal, String> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        // Alternate screen, hidden cursor, cleared
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        Ok(Terminal { saved })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+// Reads stdin on its own thread so the frame loop never blocks waiting for a key
+fn spawn_input_reader() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 64];
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        while let Ok(count) = stdin.read(&mut buffer) {
+            if count == 0 || sender.send(buffer[..count].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// How many bytes after an escape belong to the sequence it starts: `ESC [` up to its final
+// byte, `ESC O` and one more, or none if the escape stands alone. None if the rest of the
+// sequence, or whatever follows the escape, hasn't been read yet.
+fn escape_sequence_length(rest: &[u8]) -> Option<usize> {
+    match rest.first() {
+        Some(b'[') => rest[1..]
+            .iter()
+            .position(|byte| (0x40..=0x7E).contains(byte))
+            .map(|end| end + 2),
+        Some(b'O') if rest.len() < 2 => None,
+        Some(b'O') => Some(2),
+        Some(_) => Some(0),
+        None => None,
+    }
+}
+
+fn set_color(output: &mut String, layer: u8, rgb: Rgb) {
+    write!(output, "\x1b[{};2;{};{};{}m", layer, rgb.r, rgb.g, rgb.b).unwrap();
+}
+
+// Each character is two pixels stacked, the upper half block in the top pixel's colour on a
+// background of the bottom one's. Only colour changes are sent to keep the output small.
+fn render(screen: &Screen, palette: &Palette) -> String {
+    const FOREGROUND: u8 = 38;
+    const BACKGROUND: u8 = 48;
+    let width = screen.width() as usize;
+    let pixels = screen.get_pixel_data();
+    let mut output = String::from("\x1b[H");
+    let mut colors = None;
+    for (row, pair) in pixels.chunks(width * 2).enumerate() {
+        if row > 0 {
+            output.push_str("\r\n");
+        }
+        let (top, bottom) = pair.split_at(width);
+        for (&upper, &lower) in top.iter().zip(bottom) {
+            let (upper, lower) = (palette.color(upper), palette.color(lower));
+            if colors != Some((upper, lower)) {
+                set_color(&mut output, FOREGROUND, upper);
+                set_color(&mut output, BACKGROUND, lower);
+                colors = Some((upper, lower));
+            }
+            output.push('▀');
+        }
+    }
+    output.push_str("\x1b[0m");
+    output
+}
+
+fn run(cpu: &mut Cpu, options: &Options, settings: &Settings) -> Result<(), String> {
+    let instructions_per_frame = settings.instructions_per_frame();
+    let palette = settings.palette.unwrap_or_default();
+    let keymap = match &settings.keymap {
+        Some(keymap) => KeyMap::load(keymap)?,
+        None => KeyMap::default(),
+    };
+
+    let _terminal = Terminal::enter()?;
+    let input = spawn_input_reader();
+    let mut stdout = io::stdout();
+
+    // The frame each hex key is held until
+    let mut held_until = [0u64; 16];
+    let mut frame = 0;
+    let mut paused = false;
+    let mut sounding = false;
+    let mut width = 0;
+    let mut limiter = FrameLimiter::default();
+
+    'running: loop {
+        loop {
+            let mut bytes = match input.try_recv() {
+                Ok(bytes) => bytes,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'running,
+            };
+            let mut index = 0;
+            while index < bytes.len() {
+                let byte = bytes[index];
+                index += 1;
+                match byte {
+                    CTRL_C => break 'running,
+                    // A lone escape is the key, anything after it is a sequence for arrow
+                    // keys and the like, which the keymap can't name. Over a slow link a
+                    // sequence can be split across reads, so the rest gets a moment to come.
+                    ESCAPE => loop {
+                        match escape_sequence_length(&bytes[index..]) {
+                            Some(length) => {
+                                index += length;
+                                break;
+                            }
+                            None => match input.recv_timeout(ESCAPE_TIMEOUT) {
+                                Ok(more) => bytes.extend_from_slice(&more),
+                                Err(_) if index == bytes.len() => break 'running,
+                                Err(_) => {
+                                    index = bytes.len();
+                                    break;
+                                }
+                            },
+                        }
+                    },
+                    CTRL_P => paused = !paused,
+                    _ => {
+                        let host_key = (byte as char).to_string();
+                        if let Some(key) = keymap.key_for(&host_key) {
+                            held_until[key as usize] = frame + options.hold_frames;
+                        }
+                    }
+                }
+            }
+        }
+
+        if !paused {
+            let keys = (0..16)
+                .filter(|&key| held_until[key] > frame)
+                .fold(0, |keys, key| keys | 1 << key);
+            movie::set_keys(cpu, keys);
+            match frame::run_frame(cpu, instructions_per_frame, |_| true) {
+                Ok(FrameOutcome::Exited) => break 'running,
+                Ok(_) => {}
+                Err(e) => return Err(format!("Emulation halted: {}", e)),
+            }
+            frame += 1;
+        }
+
+        let screen = cpu.screen();
+        if cpu.draw_needed() || screen.width() != width {
+            if screen.width() != width {
+                // Switching resolution leaves the old picture behind otherwise
+                write!(stdout, "\x1b[2J").map_err(|e| e.to_string())?;
+                width = screen.width();
+            }
+            write!(stdout, "{}", render(screen, &palette)).map_err(|e| e.to_string())?;
+            cpu.clear_draw_flag();
+        }
+        // The bell is the closest a terminal has to a buzzer
+        let sound = !paused && cpu.is_sound_active();
+        if options.bell && sound && !sounding {
+            write!(stdout, "\x07").map_err(|e| e.to_string())?;
+        }
+        sounding = sound;
+        stdout.flush().map_err(|e| e.to_string())?;
+
+        limiter.wait(1);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let name = if args.is_empty() {
+        "chip8-tui"
+    } else {
+        &args[0][..]
+    };
+    let args = args.get(1..).unwrap_or(&[]);
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_usage(name);
+        return;
+    }
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Try {} --help", name);
+            process::exit(2);
+        }
+    };
+
+    let config = match Config::from_options(options.no_config, options.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let mut cpu = Cpu::new();
+    if let Some(seed) = options.seed {
+        cpu.seed_rng(seed);
+    }
+    if let Err(e) = cpu.load_program(&options.rom) {
+        eprintln!("Could not load program {}: {}", options.rom, e);
+        process::exit(1);
+    }
+    let settings = config.configure(&mut cpu, options.settings.clone());
+
+    // The terminal is back to normal by the time this returns, so errors show up properly
+    if let Err(e) = run(&mut cpu, &options, &settings) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
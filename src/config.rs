@@ -0,0 +1,262 @@
+use crate::cpu::Cpu;
+use crate::frame;
+use crate::keymap::KeyMap;
+use crate::palette::Palette;
+use crate::quirks::Preset;
+use crate::sha1;
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the config lives inside the config directory
+pub const CONFIG_FILE: &str = "chip8_emu/config.ini";
+
+/// Settings that can be given globally or per ROM. Unset ones fall back to the next level
+/// down: command line, ROM section, global defaults, built-in defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Instructions per second
+    pub clock: Option<u32>,
+    pub quirks: Option<Preset>,
+    pub palette: Option<Palette>,
+    /// A keymap preset name or file, as `KeyMap::load` takes
+    pub keymap: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Fills in anything unset here from `fallback`
+    pub fn or(self, fallback: &Settings) -> Settings {
+        Settings {
+            clock: self.clock.or(fallback.clock),
+            quirks: self.quirks.or(fallback.quirks),
+            palette: self.palette.or(fallback.palette),
+            keymap: self.keymap.or_else(|| fallback.keymap.clone()),
+        }
+    }
+
+    /// The clock as whole instructions per frame, or the default if it isn't set
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.clock.map_or(
+            frame::DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame::instructions_per_frame,
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// An INI style file of `key = value` lines. Lines before any section are the global
+/// defaults, and each `[sha1]` section overrides them for the ROM with that SHA-1:
+///
+/// ```ini
+/// clock = 600
+/// palette = amber
+///
+/// # Some SUPER-CHIP game
+/// [0123456789abcdef0123456789abcdef01234567]
+/// quirks = schip
+/// clock = 1200
+/// keymap = arrows.keymap
+/// ```
+///
+/// Keymaps are a preset name or a path relative to the directory the config is in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub defaults: Settings,
+    /// Keyed by lowercase hex SHA-1
+    pub roms: HashMap<String, Settings>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8_emu/config.ini`, or under `~/.config` if that isn't set
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join(CONFIG_FILE))
+    }
+
+    /// Loads a config file, or returns None if there isn't one
+    pub fn load(path: &Path) -> Result<Option<Config>, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Could not read config {}: {}", path.display(), e)),
+        };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Config::parse(&text, directory)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Loads the config at `path`, which has to exist, or the one at the default path if
+    /// there is one
+    pub fn find(path: Option<&Path>) -> Result<Config, String> {
+        let default_path = Config::default_path();
+        let (path, required) = match (path, &default_path) {
+            (Some(path), _) => (path, true),
+            (None, Some(path)) => (path.as_path(), false),
+            (None, None) => return Ok(Config::default()),
+        };
+        match Config::load(path)? {
+            Some(config) => Ok(config),
+            None if required => Err(format!("Config {} does not exist", path.display())),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// The config as the command line options ask for it: none at all with `no_config`, else
+    /// as `find` looks for it
+    pub fn from_options(no_config: bool, path: Option<&Path>) -> Result<Config, String> {
+        if no_config {
+            return Ok(Config::default());
+        }
+        Config::find(path)
+    }
+
+    /// Settles the settings for the ROM the CPU has loaded, `overrides` first and then the
+    /// config, and sets the CPU's quirks from them
+    pub fn configure(&self, cpu: &mut Cpu, overrides: Settings) -> Settings {
+        let settings = overrides.or(&self.settings_for(cpu.rom_sha1()));
+        cpu.set_quirks(settings.quirks.map(Preset::quirks).unwrap_or_default());
+        settings
+    }
+
+    /// Parses config text, resolving keymap paths against `directory`
+    pub fn parse(text: &str, directory: &Path) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut section: Option<String> = None;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ConfigError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("expected ] at the end of the section".to_string()))?
+                    .trim()
+                    .to_ascii_lowercase();
+                if name.len() != 40 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error(format!("'{}' is not a SHA-1", name)));
+                }
+                config.roms.entry(name.clone()).or_default();
+                section = Some(name);
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .map(str::trim)
+                .ok_or_else(|| error("expected <key> = <value>".to_string()))?;
+            let settings = match &section {
+                Some(name) => config.roms.get_mut(name).unwrap(),
+                None => &mut config.defaults,
+            };
+            match key {
+                "clock" => {
+                    let clock = value
+                        .parse()
+                        .ok()
+                        .filter(|&clock| clock > 0)
+                        .ok_or_else(|| error(format!("'{}' is not a clock speed", value)))?;
+                    settings.clock = Some(clock);
+                }
+                "quirks" => {
+                    settings.quirks = Some(value.parse().map_err(|e| error(format!("{}", e)))?);
+                }
+                "palette" => {
+                    settings.palette = Some(value.parse().map_err(|e| error(format!("{}", e)))?);
+                }
+                "keymap" if KeyMap::preset(value).is_some() => {
+                    settings.keymap = Some(PathBuf::from(value))
+                }
+                "keymap" => settings.keymap = Some(directory.join(value)),
+                _ => return Err(error(format!("unknown setting '{}'", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The settings for a ROM, with its section applied over the global defaults
+    pub fn settings_for(&self, rom_sha1: Option<&[u8; 20]>) -> Settings {
+        let rom = rom_sha1.and_then(|digest| self.roms.get(&sha1::to_hex(digest)));
+        match rom {
+            Some(settings) => settings.clone().or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn sections_override_the_defaults() {
+        let text = format!(
+            "# A comment\n; another\nclock = 600\nkeymap = dvorak\n\n[{}]\nquirks = schip\n\
+             clock = 1200\nkeymap = arrows.keymap\n",
+            ROM.to_ascii_uppercase()
+        );
+        let config = Config::parse(&text, Path::new("/configs")).unwrap();
+        assert_eq!(config.defaults.clock, Some(600));
+        assert_eq!(config.defaults.keymap, Some(PathBuf::from("dvorak")));
+
+        let mut digest = [0; 20];
+        for (byte, index) in digest.iter_mut().zip((0..40).step_by(2)) {
+            *byte = u8::from_str_radix(&ROM[index..index + 2], 16).unwrap();
+        }
+        let settings = config.settings_for(Some(&digest));
+        assert_eq!(settings.clock, Some(1200));
+        assert_eq!(settings.quirks, Some(Preset::SuperChip));
+        assert_eq!(
+            settings.keymap,
+            Some(PathBuf::from("/configs/arrows.keymap"))
+        );
+        assert_eq!(config.settings_for(None), config.defaults);
+    }
+
+    #[test]
+    fn bad_lines_report_their_line_number() {
+        let cases = [
+            ("clock = 600\nnonsense", 2, "expected <key> = <value>"),
+            ("\n\nspeed = 3", 3, "unknown setting 'speed'"),
+            ("clock = fast", 1, "'fast' is not a clock speed"),
+            ("clock = 0", 1, "'0' is not a clock speed"),
+            ("[0123", 1, "expected ] at the end of the section"),
+            ("# fine\n[not a hash]", 2, "'not a hash' is not a SHA-1"),
+        ];
+        for &(text, line, message) in cases.iter() {
+            let error = Config::parse(text, Path::new("")).unwrap_err();
+            assert_eq!(error.line, line, "{}", text);
+            assert_eq!(error.message, message, "{}", text);
+        }
+    }
+}
@@ -1,16 +1,75 @@
-use crate::font::FONT_SET;
+use crate::font::{BIG_FONT_ADDRESS, BIG_FONT_SET, FONT_ADDRESS, FONT_SET};
 use crate::keypad::Keypad;
-use crate::memory::Memory;
+use crate::memory::{Memory, OutOfBoundsError};
 use crate::opcode::Opcode;
+use crate::quirks::Quirks;
+use crate::random::Random;
 use crate::screen::Screen;
+use crate::sha1;
+use crate::state::{self, StateError, StateReader, StateWriter};
+use crate::trace::Tracer;
 
+use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path::Path;
-use std::time::{Duration, Instant};
 
-// TODO: Extract SDL from CPU implementation :(
-use sdl2::event::Event;
+// The pitch at which an XO-CHIP audio pattern plays back at 4000 samples per second
+const DEFAULT_PITCH: u8 = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    WaitingForKey,
+    Exited,
+}
+
+/// Something the running program did that the CPU can't carry on from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: u16 },
+    UnknownOpcode { pc: u16, opcode: u16 },
+    InvalidKey { pc: u16, key: u8 },
+    InvalidSprite { pc: u16, sprite: u8 },
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::StackOverflow { pc } => write!(f, "Stack overflow at {:#05X}", pc),
+            EmulationError::StackUnderflow { pc } => write!(f, "Stack underflow at {:#05X}", pc),
+            EmulationError::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {:#05X}", addr)
+            }
+            EmulationError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:04X} at {:#05X}", opcode, pc)
+            }
+            EmulationError::InvalidKey { pc, key } => {
+                write!(f, "Key {:#04X} does not exist, checked at {:#05X}", key, pc)
+            }
+            EmulationError::InvalidSprite { pc, sprite } => {
+                write!(
+                    f,
+                    "No font sprite for {:#04X}, requested at {:#05X}",
+                    sprite, pc
+                )
+            }
+        }
+    }
+}
+
+impl Error for EmulationError {}
+
+impl From<OutOfBoundsError> for EmulationError {
+    fn from(error: OutOfBoundsError) -> Self {
+        EmulationError::MemoryOutOfBounds {
+            addr: error.address,
+        }
+    }
+}
 
 pub struct Cpu {
     registers: [u8; 16],
@@ -22,16 +81,36 @@ pub struct Cpu {
     screen: Screen,
     keypad: Keypad,
     delay_timer: u8,
-    delay_timer_instant: Instant,
     sound_timer: u8,
-    sound_timer_instant: Instant,
     draw_flag: bool,
     waiting_for_key: bool,
     register_for_key: u8,
+    // SUPER-CHIP RPL user flags, which survive a reset like they did on the HP48
+    flags: [u8; 16],
+    exited: bool,
+    // XO-CHIP audio, a 1-bit 128 sample pattern played back at a rate set by the pitch. None
+    // until the program loads one, when the plain buzzer sounds instead.
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    quirks: Quirks,
+    rng: Random,
+    tracer: Option<Tracer>,
+    // Of the last program loaded, for looking up per-ROM settings
+    rom_sha1: Option<[u8; 20]>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut cpu = Cpu {
             registers: [0; 16],
             memory: Memory::default(),
@@ -42,15 +121,21 @@ impl Cpu {
             screen: Screen::default(),
             keypad: Keypad::default(),
             delay_timer: 0,
-            delay_timer_instant: Instant::now(),
             sound_timer: 0,
-            sound_timer_instant: Instant::now(),
             draw_flag: false,
-            waiting_for_key: true,
+            waiting_for_key: false,
             register_for_key: 0,
+            flags: [0; 16],
+            exited: false,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            quirks,
+            rng: Random::from_entropy(),
+            tracer: None,
+            rom_sha1: None,
         };
         cpu.reset();
-        return cpu;
+        cpu
     }
 
     pub fn reset(&mut self) {
@@ -64,337 +149,1112 @@ impl Cpu {
         self.program_counter = 0x200;
         self.delay_timer = 0;
         self.sound_timer = 0;
+        self.exited = false;
+        self.waiting_for_key = false;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
 
         self.load_fontset();
     }
 
-    pub fn emulate_cycle(&mut self) {
-        if !self.waiting_for_key {
-            let opcode = Opcode::from(self.memory.get_u16(self.program_counter).unwrap());
-            self.program_counter += 2;
+    pub fn emulate_cycle(&mut self) -> Result<StepOutcome, EmulationError> {
+        let outcome = if self.exited {
+            StepOutcome::Exited
+        } else if self.waiting_for_key {
+            StepOutcome::WaitingForKey
+        } else {
+            if let Some(mut tracer) = self.tracer.take() {
+                tracer.trace(self);
+                self.tracer = Some(tracer);
+            }
+            self.execute_instruction()?;
+            if self.exited {
+                StepOutcome::Exited
+            } else {
+                StepOutcome::Executed
+            }
+        };
+        Ok(outcome)
+    }
 
-            match opcode {
-                Opcode::CallAddress { address: _ } => {
-                    // TODO
-                }
-                Opcode::ClearScreen => {
-                    self.screen.reset();
-                }
-                Opcode::Return => {
-                    if self.stack_pointer == 0 {
-                        panic!("Stack underflow at address {:X}", self.program_counter);
-                    }
-                    self.stack_pointer -= 1;
-                    self.program_counter = self.stack[self.stack_pointer as usize];
-                    self.program_counter += 2;
-                }
-                Opcode::Goto { address } => {
-                    self.program_counter = address;
-                }
-                Opcode::CallSubroutine { address } => {
-                    if self.stack_pointer == 16 {
-                        panic!("Stack overflow at address {:X}!", self.program_counter);
-                    }
-                    self.stack[self.stack_pointer as usize] = self.program_counter;
-                    self.stack_pointer += 1;
-                    self.program_counter = address;
-                }
-                Opcode::IfRegEqual {
-                    register,
-                    immediate,
-                } => {
-                    assert!(register < 16);
-                    if self.registers[register as usize] == immediate {
-                        // Skip the next instruction
-                        self.program_counter += 2;
-                    }
-                }
-                Opcode::IfRegNotEqual {
-                    register,
-                    immediate,
-                } => {
-                    assert!(register < 16);
-                    if self.registers[register as usize] != immediate {
-                        // Skip the next instruction
-                        self.program_counter += 2;
-                    }
-                }
-                Opcode::IfRegsEqual {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    if self.registers[register1 as usize] == self.registers[register2 as usize] {
-                        // Skip the next instruction
-                        self.program_counter += 2;
-                    }
-                }
-                Opcode::SetRegister {
-                    register,
-                    immediate,
-                } => {
-                    assert!(register < 16);
-                    self.registers[register as usize] = immediate;
-                }
-                Opcode::AddToRegister {
-                    register,
-                    immediate,
-                } => {
-                    assert!(register < 16);
-                    self.registers[register as usize] =
-                        self.registers[register as usize].wrapping_add(immediate);
-                }
-                Opcode::MoveRegToReg {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    self.registers[register1 as usize] = self.registers[register2 as usize];
-                }
-                Opcode::BitwiseOrRegs {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let result =
-                        self.registers[register1 as usize] | self.registers[register2 as usize];
-                    self.registers[register1 as usize] = result;
-                }
-                Opcode::BitwiseAndRegs {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let result =
-                        self.registers[register1 as usize] & self.registers[register2 as usize];
-                    self.registers[register1 as usize] = result;
-                }
-                Opcode::BitwiseXorRegs {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let result =
-                        self.registers[register1 as usize] ^ self.registers[register2 as usize];
-                    self.registers[register1 as usize] = result;
-                }
-                Opcode::AddRegs {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let (result, overflow) = self.registers[register1 as usize]
-                        .overflowing_add(self.registers[register2 as usize]);
-                    self.registers[0xF] = overflow as u8;
-                    self.registers[register1 as usize] = result;
-                }
-                Opcode::SubtractRegs {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let (result, overflow) = self.registers[register1 as usize]
-                        .overflowing_sub(self.registers[register2 as usize]);
-                    self.registers[0xF] = (!overflow) as u8;
-                    self.registers[register1 as usize] = result;
+    /// Counts the delay and sound timers down. The frontend calls this 60 times a second,
+    /// however many cycles it runs in between, so the CPU itself never looks at the clock.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+
+    /// Whether the buzzer should be sounding
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    fn execute_instruction(&mut self) -> Result<(), EmulationError> {
+        let pc = self.program_counter;
+        let opcode = Opcode::from(self.memory.get_u16(pc)?);
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        match opcode {
+            Opcode::CallAddress { address: _ } => {
+                // TODO
+            }
+            Opcode::ScrollDown { lines } => {
+                self.screen.scroll_down(lines as u16);
+                self.draw_flag = true;
+            }
+            Opcode::ScrollUp { lines } => {
+                self.screen.scroll_up(lines as u16);
+                self.draw_flag = true;
+            }
+            Opcode::ClearScreen => {
+                self.screen.clear();
+                self.draw_flag = true;
+            }
+            Opcode::Return => {
+                if self.stack_pointer == 0 {
+                    return Err(EmulationError::StackUnderflow { pc });
                 }
-                Opcode::RightShiftReg { register1 } => {
-                    assert!(register1 < 16);
-                    let value = self.registers[register1 as usize];
-                    self.registers[0xF] = value & 1;
-                    self.registers[register1 as usize] = value >> 1;
+                self.stack_pointer -= 1;
+                // The stack holds the address after the call, so no need to step over it
+                self.program_counter = self.stack[self.stack_pointer as usize];
+            }
+            Opcode::ScrollRight => {
+                self.screen.scroll_right(4);
+                self.draw_flag = true;
+            }
+            Opcode::ScrollLeft => {
+                self.screen.scroll_left(4);
+                self.draw_flag = true;
+            }
+            Opcode::Exit => {
+                self.exited = true;
+            }
+            Opcode::LowRes => {
+                self.screen.set_hires(false);
+                self.draw_flag = true;
+            }
+            Opcode::HighRes => {
+                self.screen.set_hires(true);
+                self.draw_flag = true;
+            }
+            Opcode::Goto { address } => {
+                self.program_counter = address;
+            }
+            Opcode::CallSubroutine { address } => {
+                if self.stack_pointer == 16 {
+                    return Err(EmulationError::StackOverflow { pc });
                 }
-                Opcode::SubtractRegsOppositeOrder {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let (result, overflow) = self.registers[register2 as usize]
-                        .overflowing_sub(self.registers[register1 as usize]);
-                    self.registers[0xF] = (!overflow) as u8;
-                    self.registers[register1 as usize] = result;
+                self.stack[self.stack_pointer as usize] = self.program_counter;
+                self.stack_pointer += 1;
+                self.program_counter = address;
+            }
+            Opcode::IfRegEqual {
+                register,
+                immediate,
+            } => {
+                assert!(register < 16);
+                if self.registers[register as usize] == immediate {
+                    // Skip the next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::LeftShiftReg { register1 } => {
-                    assert!(register1 < 16);
-                    let value = self.registers[register1 as usize];
-                    self.registers[0xF] = value >> 7;
-                    self.registers[register1 as usize] = value << 1;
+            }
+            Opcode::IfRegNotEqual {
+                register,
+                immediate,
+            } => {
+                assert!(register < 16);
+                if self.registers[register as usize] != immediate {
+                    // Skip the next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::IfRegsNotEqual {
-                    register1,
-                    register2,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    if self.registers[register1 as usize] != self.registers[register2 as usize] {
-                        // Skip the next instruction
-                        self.program_counter += 2;
-                    }
+            }
+            Opcode::IfRegsEqual {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                if self.registers[register1 as usize] == self.registers[register2 as usize] {
+                    // Skip the next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::SetIToAddress { address } => {
-                    self.i_reg = address;
+            }
+            Opcode::SaveRegisterRange {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                for (offset, reg) in Self::register_range(register1, register2).enumerate() {
+                    self.memory.write_u8(
+                        self.i_reg.wrapping_add(offset as u16),
+                        self.registers[reg as usize],
+                    )?;
                 }
-                Opcode::JumpIndirect { address } => {
-                    self.program_counter = self.registers[0] as u16 + address;
+            }
+            Opcode::LoadRegisterRange {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                for (offset, reg) in Self::register_range(register1, register2).enumerate() {
+                    self.registers[reg as usize] =
+                        self.memory.get_u8(self.i_reg.wrapping_add(offset as u16))?;
                 }
-                Opcode::Rand {
-                    register,
-                    immediate,
-                } => {
-                    assert!(register < 16);
-                    self.registers[register as usize] = rand::random::<u8>() & immediate;
+            }
+            Opcode::SetRegister {
+                register,
+                immediate,
+            } => {
+                assert!(register < 16);
+                self.registers[register as usize] = immediate;
+            }
+            Opcode::AddToRegister {
+                register,
+                immediate,
+            } => {
+                assert!(register < 16);
+                self.registers[register as usize] =
+                    self.registers[register as usize].wrapping_add(immediate);
+            }
+            Opcode::MoveRegToReg {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                self.registers[register1 as usize] = self.registers[register2 as usize];
+            }
+            Opcode::BitwiseOrRegs {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let result =
+                    self.registers[register1 as usize] | self.registers[register2 as usize];
+                self.registers[register1 as usize] = result;
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
                 }
-                Opcode::Draw {
-                    register1,
-                    register2,
-                    height,
-                } => {
-                    assert!(register1 < 16 && register2 < 16);
-                    let sprite = self.memory.get_data(self.i_reg, height as u16).unwrap();
-                    let x = self.registers[register1 as usize];
-                    let y = self.registers[register2 as usize];
-                    self.registers[0xF] = if self.screen.draw_sprite(x as u16, y as u16, sprite) {
-                        1
-                    } else {
-                        0
-                    };
-                    self.draw_flag = true;
+            }
+            Opcode::BitwiseAndRegs {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let result =
+                    self.registers[register1 as usize] & self.registers[register2 as usize];
+                self.registers[register1 as usize] = result;
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
                 }
-                Opcode::IfKeyEqual { register } => {
-                    assert!(register < 16);
-                    let is_pressed = self
-                        .keypad
-                        .is_key_pressed(self.registers[register as usize]);
-                    if is_pressed {
-                        // Skip next instruction
-                        self.program_counter += 2
-                    }
+            }
+            Opcode::BitwiseXorRegs {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let result =
+                    self.registers[register1 as usize] ^ self.registers[register2 as usize];
+                self.registers[register1 as usize] = result;
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xF] = 0;
                 }
-                Opcode::IfKeyNotEqual { register } => {
-                    assert!(register < 16);
-                    let is_pressed = self
-                        .keypad
-                        .is_key_pressed(self.registers[register as usize]);
-                    if !is_pressed {
-                        // Skip next instruction
-                        self.program_counter += 2
-                    }
+            }
+            Opcode::AddRegs {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let (result, overflow) = self.registers[register1 as usize]
+                    .overflowing_add(self.registers[register2 as usize]);
+                self.registers[register1 as usize] = result;
+                self.registers[0xF] = overflow as u8;
+            }
+            Opcode::SubtractRegs {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let (result, overflow) = self.registers[register1 as usize]
+                    .overflowing_sub(self.registers[register2 as usize]);
+                self.registers[register1 as usize] = result;
+                self.registers[0xF] = (!overflow) as u8;
+            }
+            Opcode::RightShiftReg {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let source = if self.quirks.shift_uses_vy {
+                    register2
+                } else {
+                    register1
+                };
+                let value = self.registers[source as usize];
+                self.registers[register1 as usize] = value >> 1;
+                self.registers[0xF] = value & 1;
+            }
+            Opcode::SubtractRegsOppositeOrder {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let (result, overflow) = self.registers[register2 as usize]
+                    .overflowing_sub(self.registers[register1 as usize]);
+                self.registers[register1 as usize] = result;
+                self.registers[0xF] = (!overflow) as u8;
+            }
+            Opcode::LeftShiftReg {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let source = if self.quirks.shift_uses_vy {
+                    register2
+                } else {
+                    register1
+                };
+                let value = self.registers[source as usize];
+                self.registers[register1 as usize] = value << 1;
+                self.registers[0xF] = value >> 7;
+            }
+            Opcode::IfRegsNotEqual {
+                register1,
+                register2,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                if self.registers[register1 as usize] != self.registers[register2 as usize] {
+                    // Skip the next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::GetDelay { register } => {
-                    assert!(register < 16);
-                    self.registers[register as usize] = self.delay_timer;
+            }
+            Opcode::SetIToAddress { address } => {
+                self.i_reg = address;
+            }
+            Opcode::JumpIndirect { address } => {
+                let register = if self.quirks.jump_uses_vx {
+                    (address >> 8) as usize
+                } else {
+                    0
+                };
+                self.program_counter = address.wrapping_add(self.registers[register] as u16);
+            }
+            Opcode::Rand {
+                register,
+                immediate,
+            } => {
+                assert!(register < 16);
+                self.registers[register as usize] = self.rng.next_u8() & immediate;
+            }
+            Opcode::Draw {
+                register1,
+                register2,
+                height,
+            } => {
+                assert!(register1 < 16 && register2 < 16);
+                let x = self.registers[register1 as usize] as u16;
+                let y = self.registers[register2 as usize] as u16;
+                let wrap = self.quirks.wrap_sprites;
+                // Each selected plane has its own sprite data, one after the other
+                let planes = self.screen.selected_plane_count();
+                let collision = if height == 0 {
+                    let sprite = self.memory.get_data(self.i_reg, 32 * planes)?;
+                    self.screen.draw_large_sprite(x, y, sprite, wrap)
+                } else {
+                    let size = height as u16 * planes;
+                    let sprite = self.memory.get_data(self.i_reg, size)?;
+                    self.screen.draw_sprite(x, y, sprite, wrap)
+                };
+                self.registers[0xF] = if collision { 1 } else { 0 };
+                self.draw_flag = true;
+            }
+            Opcode::IfKeyEqual { register } => {
+                assert!(register < 16);
+                let key = self.registers[register as usize];
+                if key >= 16 {
+                    return Err(EmulationError::InvalidKey { pc, key });
                 }
-                Opcode::GetKey { register } => {
-                    assert!(register < 16);
-                    self.waiting_for_key = true;
-                    self.register_for_key = register;
+                let is_pressed = self.keypad.is_key_pressed(key);
+                if is_pressed {
+                    // Skip next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::SetDelay { register } => {
-                    assert!(register < 16);
-                    self.delay_timer = self.registers[register as usize];
-                    self.delay_timer_instant = Instant::now();
+            }
+            Opcode::IfKeyNotEqual { register } => {
+                assert!(register < 16);
+                let key = self.registers[register as usize];
+                if key >= 16 {
+                    return Err(EmulationError::InvalidKey { pc, key });
                 }
-                Opcode::SetSound { register } => {
-                    assert!(register < 16);
-                    self.sound_timer = self.registers[register as usize];
-                    self.sound_timer_instant = Instant::now();
+                let is_pressed = self.keypad.is_key_pressed(key);
+                if !is_pressed {
+                    // Skip next instruction
+                    self.skip_next_instruction();
                 }
-                Opcode::AddRegToI { register } => {
-                    assert!(register < 16);
-                    self.i_reg = self.i_reg + self.registers[register as usize] as u16;
-                    if self.i_reg > 0xFFF {
-                        // wrap
-                        self.i_reg -= 0xFFF;
-                    }
+            }
+            Opcode::SetILong => {
+                self.i_reg = self.memory.get_u16(self.program_counter)?;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Opcode::SelectPlanes { planes } => {
+                self.screen.select_planes(planes);
+            }
+            Opcode::LoadAudioPattern => {
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(self.memory.get_data(self.i_reg, 16)?);
+                self.audio_pattern = Some(pattern);
+            }
+            Opcode::GetDelay { register } => {
+                assert!(register < 16);
+                self.registers[register as usize] = self.delay_timer;
+            }
+            Opcode::GetKey { register } => {
+                assert!(register < 16);
+                self.waiting_for_key = true;
+                self.register_for_key = register;
+            }
+            Opcode::SetDelay { register } => {
+                assert!(register < 16);
+                self.delay_timer = self.registers[register as usize];
+            }
+            Opcode::SetSound { register } => {
+                assert!(register < 16);
+                self.sound_timer = self.registers[register as usize];
+            }
+            Opcode::AddRegToI { register } => {
+                assert!(register < 16);
+                self.i_reg = self
+                    .i_reg
+                    .wrapping_add(self.registers[register as usize] as u16);
+            }
+            Opcode::GetSpriteAddr { register } => {
+                assert!(register < 16);
+                let sprite = self.registers[register as usize];
+                if sprite >= 16 {
+                    return Err(EmulationError::InvalidSprite { pc, sprite });
                 }
-                Opcode::GetSpriteAddr { register } => {
-                    assert!(register < 16);
-                    let sprite = self.registers[register as usize];
-                    assert!(sprite < 16);
-                    // font map starts at 0x50, and each sprite is 5 bytes
-                    self.i_reg = 0x50 + (sprite as u16) * 5;
+                // each sprite is 5 bytes
+                self.i_reg = FONT_ADDRESS + (sprite as u16) * 5;
+            }
+            Opcode::GetBigSpriteAddr { register } => {
+                assert!(register < 16);
+                let sprite = self.registers[register as usize];
+                if sprite >= 16 {
+                    return Err(EmulationError::InvalidSprite { pc, sprite });
                 }
-                Opcode::ToBinaryCodedDecimal { register } => {
-                    assert!(register < 16);
-                    let value_to_convert = self.registers[register as usize];
-                    // I'm not sure if this is the best way to do this, but it works
-                    self.memory
-                        .write_u8(self.i_reg, value_to_convert / 100)
-                        .unwrap();
-                    self.memory
-                        .write_u8(self.i_reg + 1, (value_to_convert % 100) / 10)
-                        .unwrap();
+                // each big sprite is 10 bytes
+                self.i_reg = BIG_FONT_ADDRESS + (sprite as u16) * 10;
+            }
+            Opcode::ToBinaryCodedDecimal { register } => {
+                assert!(register < 16);
+                let value_to_convert = self.registers[register as usize];
+                // I'm not sure if this is the best way to do this, but it works
+                self.memory.write_u8(self.i_reg, value_to_convert / 100)?;
+                self.memory
+                    .write_u8(self.i_reg.wrapping_add(1), (value_to_convert % 100) / 10)?;
+                self.memory
+                    .write_u8(self.i_reg.wrapping_add(2), value_to_convert % 10)?;
+            }
+            Opcode::SetPitch { register } => {
+                assert!(register < 16);
+                self.pitch = self.registers[register as usize];
+            }
+            Opcode::DumpRegistersUntil { register } => {
+                assert!(register < 16);
+                for reg in 0..=register as u16 {
                     self.memory
-                        .write_u8(self.i_reg + 2, value_to_convert % 10)
-                        .unwrap();
+                        .write_u8(self.i_reg.wrapping_add(reg), self.registers[reg as usize])?;
                 }
-                Opcode::DumpRegistersUntil { register } => {
-                    assert!(register < 16);
-                    for reg in 0..=register as u16 {
-                        self.memory
-                            .write_u8(self.i_reg + reg, self.registers[reg as usize])
-                            .unwrap();
-                    }
+                if self.quirks.load_store_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(register as u16 + 1);
                 }
-                Opcode::LoadRegistersUntil { register } => {
-                    assert!(register < 16);
-                    for reg in 0..=register as u16 {
-                        self.registers[reg as usize] =
-                            self.memory.get_u8(self.i_reg + reg).unwrap();
-                    }
+            }
+            Opcode::LoadRegistersUntil { register } => {
+                assert!(register < 16);
+                for reg in 0..=register as u16 {
+                    self.registers[reg as usize] =
+                        self.memory.get_u8(self.i_reg.wrapping_add(reg))?;
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i_reg = self.i_reg.wrapping_add(register as u16 + 1);
                 }
-                Opcode::Unknown { opcode } => eprintln!("Unknown opcode {:X}", opcode),
             }
-
-            while self.program_counter > 0xFFF {
-                self.program_counter -= 0xFFF;
+            Opcode::SaveFlagsUntil { register } => {
+                assert!(register < 16);
+                let count = register as usize + 1;
+                self.flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Opcode::LoadFlagsUntil { register } => {
+                assert!(register < 16);
+                let count = register as usize + 1;
+                self.registers[..count].copy_from_slice(&self.flags[..count]);
+            }
+            Opcode::Unknown { opcode } => {
+                return Err(EmulationError::UnknownOpcode { pc, opcode });
             }
         }
 
-        const COUNTDOWN_RATE: u64 = 60; // HZ
-        const NANOS_PER_SECOND: u64 = 1_000_000_000;
-        const TIME_BETWEEN_COUNTS: u64 = NANOS_PER_SECOND / COUNTDOWN_RATE;
-        let now = Instant::now();
-        if self.delay_timer > 0 {
-            if now - self.delay_timer_instant > Duration::from_nanos(TIME_BETWEEN_COUNTS) {
-                self.delay_timer -= 1;
-                self.delay_timer_instant = now;
-            }
+        Ok(())
+    }
+
+    // XO-CHIP's F000 NNNN is twice the size of other instructions, so skipping it takes 4 bytes
+    fn skip_next_instruction(&mut self) {
+        let next = self.memory.get_u16(self.program_counter).unwrap_or(0);
+        let size = if next == 0xF000 { 4 } else { 2 };
+        self.program_counter = self.program_counter.wrapping_add(size);
+    }
+
+    // 5XY2/5XY3 go from VX to VY, backwards if Y < X
+    fn register_range(register1: u8, register2: u8) -> Box<dyn Iterator<Item = u8>> {
+        if register1 <= register2 {
+            Box::new(register1..=register2)
+        } else {
+            Box::new((register2..=register1).rev())
         }
+    }
 
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP!");
-            }
-            if now - self.sound_timer_instant > Duration::from_secs(TIME_BETWEEN_COUNTS) {
-                self.sound_timer -= 1;
-                self.sound_timer_instant = now;
-            }
+    /// Snapshots everything the running program can observe into a versioned binary blob
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(&self.registers);
+        writer.write_u16(self.i_reg);
+        writer.write_u8(self.stack_pointer);
+        for &address in self.stack.iter() {
+            writer.write_u16(address);
         }
+        writer.write_u16(self.program_counter);
+        writer.write_u8(self.delay_timer);
+        writer.write_u8(self.sound_timer);
+        writer.write_bool(self.draw_flag);
+        writer.write_bool(self.waiting_for_key);
+        writer.write_u8(self.register_for_key);
+        writer.write_bytes(&self.flags);
+        writer.write_bool(self.exited);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or_default());
+        writer.write_u8(self.pitch);
+        self.rng.save_state(&mut writer);
+        self.memory.save_state(&mut writer);
+        self.screen.save_state(&mut writer);
+        self.keypad.save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a snapshot made by `save_state`. The CPU is left untouched if the state is
+    /// invalid. Quirks, the tracer and the ROM hash aren't part of the state, so are kept as
+    /// they are.
+    pub fn load_state(&mut self, data: &[u8]) -> state::Result<()> {
+        let mut reader = StateReader::new(data)?;
+        let mut loaded = Cpu::with_quirks(self.quirks);
+        reader.read_into(&mut loaded.registers)?;
+        loaded.i_reg = reader.read_u16()?;
+        loaded.stack_pointer = reader.read_u8()?;
+        if loaded.stack_pointer as usize > loaded.stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        for address in loaded.stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        loaded.program_counter = reader.read_u16()?;
+        loaded.delay_timer = reader.read_u8()?;
+        loaded.sound_timer = reader.read_u8()?;
+        loaded.draw_flag = reader.read_bool()?;
+        loaded.waiting_for_key = reader.read_bool()?;
+        loaded.register_for_key = reader.read_u8()?;
+        if loaded.register_for_key >= 16 {
+            return Err(StateError::Invalid("register"));
+        }
+        reader.read_into(&mut loaded.flags)?;
+        loaded.exited = reader.read_bool()?;
+        let pattern_loaded = reader.read_bool()?;
+        let mut pattern = [0; 16];
+        reader.read_into(&mut pattern)?;
+        loaded.audio_pattern = if pattern_loaded { Some(pattern) } else { None };
+        loaded.pitch = reader.read_u8()?;
+        loaded.rng.load_state(&mut reader)?;
+        loaded.memory.load_state(&mut reader)?;
+        loaded.screen.load_state(&mut reader)?;
+        loaded.keypad.load_state(&mut reader)?;
+        reader.finish()?;
+
+        loaded.tracer = self.tracer.take();
+        loaded.rom_sha1 = self.rom_sha1;
+        *self = loaded;
+        Ok(())
     }
 
     pub fn load_program<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
         let program = fs::read(path)?;
-        self.memory.write_data(0x200, &program[..]).unwrap();
+        self.memory.write_data(0x200, &program[..]).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Program is too large ({} bytes)", program.len()),
+            )
+        })?;
+        self.rom_sha1 = Some(sha1::sha1(&program));
         Ok(())
     }
 
+    /// The SHA-1 of the last program loaded
+    pub fn rom_sha1(&self) -> Option<&[u8; 20]> {
+        self.rom_sha1.as_ref()
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Makes RND produce the same numbers every run
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Random::new(seed);
+    }
+
+    /// The seed RND started from, which `seed_rng` sets or is picked at random otherwise
+    pub fn rng_seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    /// Starts logging every executed instruction, or stops if `tracer` is None
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
     pub fn draw_needed(&self) -> bool {
         self.draw_flag
     }
 
+    /// Called by the frontend once it has drawn the screen
+    pub fn clear_draw_flag(&mut self) {
+        self.draw_flag = false;
+    }
+
     pub fn get_pixel_data(&self) -> &[u8] {
         self.screen.get_pixel_data()
     }
 
-    pub fn handle_input(&mut self, event: Event) {
-        let handled = self.keypad.handle_input(event);
-        if self.waiting_for_key && handled {
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        assert!(register < 16);
+        self.registers[register as usize] = value;
+    }
+
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    pub fn set_i_reg(&mut self, value: u16) {
+        self.i_reg = value;
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address;
+    }
+
+    /// The return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// The XO-CHIP audio pattern buffer, 128 1-bit samples played most significant bit first,
+    /// or None if the program hasn't loaded one with F002
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// The rate in samples per second at which the audio pattern should be played
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Whether the program has run 00FD and stopped
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.keypad.press_key(key);
+        if self.waiting_for_key {
             self.waiting_for_key = false;
             self.registers[self.register_for_key as usize] = self.keypad.get_last_key();
         }
     }
 
+    pub fn release_key(&mut self, key: u8) {
+        self.keypad.release_key(key);
+    }
+
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.keypad.is_key_pressed(key)
+    }
+
     fn load_fontset(&mut self) {
-        self.memory.write_data(0x50, &FONT_SET[..]).unwrap();
+        self.memory.write_data(FONT_ADDRESS, &FONT_SET[..]).unwrap();
+        self.memory
+            .write_data(BIG_FONT_ADDRESS, &BIG_FONT_SET[..])
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Preset;
+
+    // A CPU with `program` loaded where programs start
+    fn cpu_with(preset: Preset, program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::with_quirks(preset.quirks());
+        cpu.seed_rng(1);
+        cpu.memory_mut().write_data(0x200, program).unwrap();
+        cpu
+    }
+
+    fn step(cpu: &mut Cpu, count: usize) {
+        for _ in 0..count {
+            cpu.emulate_cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn calls_return_to_the_instruction_after_the_call() {
+        let mut cpu = cpu_with(
+            Preset::CosmacVip,
+            &[0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x61, 0x02, 0x00, 0xEE],
+        );
+        step(&mut cpu, 3);
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert!(cpu.stack().is_empty());
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn the_seventeenth_nested_call_overflows_the_stack() {
+        let mut cpu = cpu_with(Preset::CosmacVip, &[0x22, 0x00]);
+        step(&mut cpu, 16);
+        assert_eq!(cpu.stack().len(), 16);
+        assert_eq!(
+            cpu.emulate_cycle(),
+            Err(EmulationError::StackOverflow { pc: 0x200 })
+        );
+    }
+
+    #[test]
+    fn returning_with_an_empty_stack_underflows() {
+        let mut cpu = cpu_with(Preset::CosmacVip, &[0x00, 0xEE]);
+        assert_eq!(
+            cpu.emulate_cycle(),
+            Err(EmulationError::StackUnderflow { pc: 0x200 })
+        );
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_is_an_error() {
+        let mut cpu = cpu_with(Preset::CosmacVip, &[]);
+        cpu.set_program_counter(0xFFFF);
+        assert_eq!(
+            cpu.emulate_cycle(),
+            Err(EmulationError::MemoryOutOfBounds { addr: 0xFFFF })
+        );
+    }
+
+    #[test]
+    fn high_resolution_doubles_the_screen_and_clears_it() {
+        let mut cpu = cpu_with(
+            Preset::SuperChip,
+            &[0x00, 0xFF, 0xF0, 0x29, 0xD0, 0x15, 0x00, 0xFE],
+        );
+        step(&mut cpu, 3);
+        assert!(cpu.screen().is_hires());
+        assert_eq!((cpu.screen().width(), cpu.screen().height()), (128, 64));
+        assert_ne!(cpu.screen().get_pixel(0, 0), 0);
+
+        step(&mut cpu, 1);
+        assert!(!cpu.screen().is_hires());
+        assert_eq!((cpu.screen().width(), cpu.screen().height()), (64, 32));
+        assert_eq!(cpu.screen().get_pixel(0, 0), 0);
+    }
+
+    #[test]
+    fn scrolling_moves_the_picture() {
+        let mut cpu = cpu_with(
+            Preset::SuperChip,
+            &[
+                0xD0, 0x11, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC, 0x00, 0xFC,
+            ],
+        );
+        cpu.memory_mut().write_u8(0x300, 0x80).unwrap();
+        cpu.set_i_reg(0x300);
+        cpu.set_register(0, 5);
+        cpu.set_register(1, 5);
+        // Where the only lit pixel is after each step, until the last scroll pushes it off
+        for &expected in [(5, 5), (5, 8), (9, 8), (5, 8), (1, 8)].iter() {
+            step(&mut cpu, 1);
+            assert_ne!(cpu.screen().get_pixel(expected.0, expected.1), 0);
+            assert_eq!(cpu.get_pixel_data().iter().filter(|&&p| p != 0).count(), 1);
+        }
+        step(&mut cpu, 1);
+        assert!(cpu.get_pixel_data().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn large_sprites_are_sixteen_pixels_square() {
+        let mut cpu = cpu_with(Preset::SuperChip, &[0x00, 0xFF, 0xD0, 0x00, 0xD0, 0x00]);
+        cpu.memory_mut().write_data(0x300, &[0xFF; 32]).unwrap();
+        cpu.set_i_reg(0x300);
+        step(&mut cpu, 2);
+        assert_eq!(cpu.registers()[0xF], 0);
+        assert_eq!(
+            cpu.get_pixel_data().iter().filter(|&&p| p != 0).count(),
+            256
+        );
+        assert_ne!(cpu.screen().get_pixel(15, 15), 0);
+        assert_eq!(cpu.screen().get_pixel(16, 0), 0);
+
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[0xF], 1);
+        assert!(cpu.get_pixel_data().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn big_font_sprites_are_ten_bytes_each() {
+        let mut cpu = cpu_with(Preset::SuperChip, &[0xF0, 0x30]);
+        cpu.set_register(0, 9);
+        step(&mut cpu, 1);
+        assert_eq!(cpu.i_reg(), BIG_FONT_ADDRESS + 90);
+    }
+
+    #[test]
+    fn user_flags_survive_a_reset() {
+        let mut cpu = cpu_with(Preset::SuperChip, &[0xF2, 0x75]);
+        for register in 0..4 {
+            cpu.set_register(register, register + 1);
+        }
+        step(&mut cpu, 1);
+
+        cpu.reset();
+        cpu.memory_mut().write_data(0x200, &[0xF3, 0x85]).unwrap();
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn exit_stops_the_program() {
+        let mut cpu = cpu_with(Preset::SuperChip, &[0x00, 0xFD, 0x60, 0x01]);
+        assert_eq!(cpu.emulate_cycle(), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.emulate_cycle(), Ok(StepOutcome::Exited));
+        assert!(cpu.has_exited());
+        assert_eq!(cpu.registers()[0], 0);
+    }
+
+    #[test]
+    fn scrolling_up_moves_the_picture_up() {
+        let mut cpu = cpu_with(Preset::XoChip, &[0xD0, 0x11, 0x00, 0xD3]);
+        cpu.memory_mut().write_u8(0x300, 0x80).unwrap();
+        cpu.set_i_reg(0x300);
+        cpu.set_register(1, 5);
+        step(&mut cpu, 2);
+        assert_ne!(cpu.screen().get_pixel(0, 2), 0);
+        assert_eq!(cpu.screen().get_pixel(0, 5), 0);
+    }
+
+    #[test]
+    fn drawing_and_clearing_only_touch_the_selected_planes() {
+        let mut cpu = cpu_with(
+            Preset::XoChip,
+            &[
+                0xF2, 0x01, 0xD0, 0x01, 0xF1, 0x01, 0x00, 0xE0, 0xF3, 0x01, 0xD0, 0x01,
+            ],
+        );
+        cpu.memory_mut().write_data(0x300, &[0x80, 0x80]).unwrap();
+        cpu.set_i_reg(0x300);
+        step(&mut cpu, 2);
+        assert_eq!(cpu.screen().get_pixel(0, 0), 0b10);
+
+        step(&mut cpu, 2);
+        assert_eq!(cpu.screen().get_pixel(0, 0), 0b10);
+
+        // With both planes selected the sprite holds a line for each, first plane first
+        step(&mut cpu, 2);
+        assert_eq!(cpu.screen().get_pixel(0, 0), 0b01);
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn long_load_takes_the_address_from_the_next_word() {
+        let mut cpu = cpu_with(Preset::XoChip, &[0xF0, 0x00, 0xAB, 0xCD, 0xF1, 0x65]);
+        cpu.memory_mut().write_data(0xABCD, &[7, 8]).unwrap();
+        step(&mut cpu, 1);
+        assert_eq!(cpu.i_reg(), 0xABCD);
+        assert_eq!(cpu.program_counter(), 0x204);
+
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[..2], [7, 8]);
+    }
+
+    #[test]
+    fn skips_step_over_all_of_a_long_load() {
+        for &(skip, pc) in [(0x30, 0x206), (0x40, 0x202)].iter() {
+            let mut cpu = cpu_with(
+                Preset::XoChip,
+                &[skip, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x60, 0x01],
+            );
+            step(&mut cpu, 1);
+            assert_eq!(cpu.program_counter(), pc, "{:02X}00", skip);
+        }
+    }
+
+    #[test]
+    fn register_ranges_go_either_way_and_leave_i_alone() {
+        let mut cpu = cpu_with(Preset::XoChip, &[0x51, 0x32, 0x53, 0x12, 0x56, 0x43]);
+        for register in 1..4 {
+            cpu.set_register(register, register);
+        }
+        cpu.set_i_reg(0x300);
+        step(&mut cpu, 1);
+        assert_eq!(cpu.memory().peek(0x300, 4), &[1, 2, 3, 0]);
+
+        cpu.set_i_reg(0x310);
+        step(&mut cpu, 1);
+        assert_eq!(cpu.memory().peek(0x310, 4), &[3, 2, 1, 0]);
+
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[..8], [0, 1, 2, 3, 1, 2, 3, 0]);
+        assert_eq!(cpu.i_reg(), 0x310);
+    }
+
+    #[test]
+    fn registers_save_and_load_anywhere_in_memory() {
+        let mut cpu = cpu_with(Preset::XoChip, &[0xF2, 0x55, 0xF2, 0x65]);
+        for register in 0..3 {
+            cpu.set_register(register, 0xA0 + register);
+        }
+        cpu.set_i_reg(0xFFF0);
+        step(&mut cpu, 1);
+        assert_eq!(cpu.memory().peek(0xFFF0, 3), &[0xA0, 0xA1, 0xA2]);
+
+        cpu.set_i_reg(0xFFF0);
+        for register in 0..3 {
+            cpu.set_register(register, 0);
+        }
+        step(&mut cpu, 1);
+        assert_eq!(cpu.registers()[..3], [0xA0, 0xA1, 0xA2]);
+    }
+
+    // Draws font sprites at random places forever
+    const RANDOM_DRAWING: [u8; 12] = [
+        0xC0, 0x3F, 0xC1, 0x1F, 0xA0, 0x00, 0xD0, 0x15, 0x72, 0x01, 0x12, 0x00,
+    ];
+
+    #[test]
+    fn a_loaded_state_runs_on_the_same_as_the_original() {
+        let mut cpu = cpu_with(Preset::CosmacVip, &RANDOM_DRAWING);
+        step(&mut cpu, 50);
+        cpu.set_delay_timer(30);
+        cpu.press_key(5);
+        let saved = cpu.save_state();
+
+        step(&mut cpu, 100);
+        cpu.tick_timers();
+        let expected = cpu.save_state();
+
+        let mut restored = cpu_with(Preset::CosmacVip, &[]);
+        restored.load_state(&saved).unwrap();
+        assert_eq!(restored.save_state(), saved);
+        step(&mut restored, 100);
+        restored.tick_timers();
+        assert_eq!(restored.save_state(), expected);
+    }
+
+    #[test]
+    fn a_rejected_state_leaves_the_cpu_alone() {
+        let mut cpu = cpu_with(Preset::CosmacVip, &RANDOM_DRAWING);
+        step(&mut cpu, 20);
+        let before = cpu.save_state();
+
+        let mut bad_magic = before.clone();
+        bad_magic[0] = b'X';
+        let mut bad_version = before.clone();
+        bad_version[4] = state::VERSION + 1;
+        // The draw flag comes after the header, registers, I, stack and timers
+        let mut bad_flag = before.clone();
+        bad_flag[60] = 2;
+        let mut trailing = before.clone();
+        trailing.push(0);
+        let cases = [
+            (&bad_magic[..], StateError::BadMagic),
+            (
+                &bad_version[..],
+                StateError::UnsupportedVersion(state::VERSION + 1),
+            ),
+            (&before[..before.len() - 1], StateError::Truncated),
+            (&trailing[..], StateError::TrailingData),
+            (&bad_flag[..], StateError::Invalid("flag")),
+        ];
+        for (data, error) in cases.iter() {
+            assert_eq!(cpu.load_state(data), Err(*error));
+            assert_eq!(cpu.save_state(), before);
+        }
+    }
+
+    #[test]
+    fn shifts_use_vy_only_with_the_quirk() {
+        for &(preset, right, left) in [
+            (Preset::CosmacVip, (0x40, 1), (0x02, 1)),
+            (Preset::SuperChip, (0x01, 0), (0x04, 0)),
+        ]
+        .iter()
+        {
+            let mut cpu = cpu_with(preset, &[0x80, 0x16, 0x80, 0x1E]);
+            cpu.set_register(0, 0x02);
+            cpu.set_register(1, 0x81);
+            step(&mut cpu, 1);
+            assert_eq!((cpu.registers()[0], cpu.registers()[0xF]), right);
+
+            cpu.set_register(0, 0x02);
+            step(&mut cpu, 1);
+            assert_eq!((cpu.registers()[0], cpu.registers()[0xF]), left);
+        }
+    }
+
+    #[test]
+    fn load_and_store_move_i_only_with_the_quirk() {
+        // (preset, I after storing, I after loading back from there)
+        for &(preset, stored, loaded) in [
+            (Preset::CosmacVip, 0x303, 0x306),
+            (Preset::SuperChip, 0x300, 0x300),
+        ]
+        .iter()
+        {
+            let mut cpu = cpu_with(preset, &[0xF2, 0x55, 0xF2, 0x65]);
+            for register in 0..3 {
+                cpu.set_register(register, 10 + register);
+            }
+            cpu.set_i_reg(0x300);
+            step(&mut cpu, 1);
+            assert_eq!(cpu.memory().peek(0x300, 4), &[10, 11, 12, 0]);
+            assert_eq!(cpu.i_reg(), stored);
+
+            cpu.memory_mut().write_data(stored, &[20, 21, 22]).unwrap();
+            step(&mut cpu, 1);
+            assert_eq!(cpu.registers()[..3], [20, 21, 22]);
+            assert_eq!(cpu.i_reg(), loaded);
+        }
+    }
+
+    #[test]
+    fn jump_with_offset_uses_vx_only_with_the_quirk() {
+        for &(preset, target) in [(Preset::CosmacVip, 0x211), (Preset::SuperChip, 0x215)].iter() {
+            let mut cpu = cpu_with(preset, &[0xB2, 0x10]);
+            cpu.set_register(0, 1);
+            cpu.set_register(2, 5);
+            step(&mut cpu, 1);
+            assert_eq!(cpu.program_counter(), target);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_only_with_the_quirk() {
+        for &(preset, vf) in [(Preset::CosmacVip, 0), (Preset::SuperChip, 5)].iter() {
+            for &opcode in [0x11, 0x12, 0x13].iter() {
+                let mut cpu = cpu_with(preset, &[0x80, opcode]);
+                cpu.set_register(0xF, 5);
+                step(&mut cpu, 1);
+                assert_eq!(cpu.registers()[0xF], vf, "8X{:02X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn sprites_clip_at_the_edge_unless_they_wrap() {
+        for &(preset, wrapped) in [(Preset::CosmacVip, false), (Preset::XoChip, true)].iter() {
+            let mut cpu = cpu_with(preset, &[0xD0, 0x11]);
+            cpu.memory_mut().write_u8(0x300, 0xFF).unwrap();
+            cpu.set_i_reg(0x300);
+            cpu.set_register(0, 62);
+            step(&mut cpu, 1);
+            assert_ne!(cpu.screen().get_pixel(63, 0), 0);
+            assert_eq!(cpu.screen().get_pixel(0, 0) != 0, wrapped);
+            assert_eq!(cpu.screen().get_pixel(5, 0) != 0, wrapped);
+            assert_eq!(cpu.screen().get_pixel(6, 0), 0);
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_vf_leaves_the_flag_in_vf() {
+        // (opcode, VF, VE, VF afterwards), the result going to VF before the flag replaces it
+        let cases = [
+            (0x8FE4u16, 0xFF, 0x01, 1),
+            (0x8FE4, 0x01, 0x01, 0),
+            (0x8FE5, 0x01, 0x02, 0),
+            (0x8FE5, 0x03, 0x01, 1),
+            (0x8FE7, 0x03, 0x01, 0),
+            (0x8FE7, 0x01, 0x03, 1),
+            (0x8F06, 0x02, 0x00, 0),
+            (0x8F06, 0x01, 0x00, 1),
+            (0x8F0E, 0x81, 0x00, 1),
+            (0x8F0E, 0x01, 0x00, 0),
+        ];
+        for &(opcode, vf, ve, flag) in cases.iter() {
+            let mut cpu = cpu_with(Preset::SuperChip, &opcode.to_be_bytes());
+            cpu.set_register(0xF, vf);
+            cpu.set_register(0xE, ve);
+            step(&mut cpu, 1);
+            assert_eq!(
+                cpu.registers()[0xF],
+                flag,
+                "{:04X} with VF={:02X}",
+                opcode,
+                vf
+            );
+        }
     }
 }
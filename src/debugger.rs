@@ -0,0 +1,254 @@
+use crate::cpu::Cpu;
+use crate::headless;
+use crate::memory::MEMORY_SIZE;
+use crate::util::parse_number;
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+pub const HELP: &str = "\
+Numbers are decimal, or hex with a 0x prefix.
+  b, break <addr>          Break when PC reaches addr
+  d, delete <addr>         Remove the breakpoint at addr
+  bl, breakpoints          List breakpoints
+  s, step [n]              Execute n instructions (default 1)
+  c, continue              Run until the next breakpoint
+  r, regs                  Print V0-VF, I, PC, SP and timers
+  stack                    Print the return addresses on the stack
+  m, mem <addr> [len]      Dump len bytes of memory (default 64)
+  set <v0-vf|i|pc|dt|st> <value>
+                           Set a register or timer
+  poke <addr> <byte>...    Write bytes to memory
+  h, help                  Print this help
+  q, quit                  Quit the emulator";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RunState {
+    Paused,
+    Stepping(u64),
+    Running,
+}
+
+/// What the frontend should do after a command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// Keep reading commands
+    Stay,
+    /// Go back to running the CPU
+    Resume,
+    Quit,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    state: RunState,
+    // The address execution resumed from, so its breakpoint doesn't fire again immediately
+    resumed_at: Option<u16>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// A debugger that starts out paused
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            state: RunState::Paused,
+            resumed_at: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Called before every cycle, returns whether to stop and read commands instead
+    pub fn should_pause(&mut self, cpu: &Cpu) -> bool {
+        match self.state {
+            RunState::Paused => true,
+            RunState::Stepping(0) => {
+                self.state = RunState::Paused;
+                true
+            }
+            RunState::Stepping(steps) => {
+                self.state = RunState::Stepping(steps - 1);
+                false
+            }
+            RunState::Running => {
+                let pc = cpu.program_counter();
+                let resumed_here = self.resumed_at.take() == Some(pc);
+                if !resumed_here && self.breakpoints.contains(&pc) {
+                    self.state = RunState::Paused;
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    /// Parses and runs one command line, returning its output
+    pub fn execute(&mut self, cpu: &mut Cpu, line: &str) -> Result<(String, Control), String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok((String::new(), Control::Stay)),
+        };
+        let args = words.collect::<Vec<_>>();
+        let mut output = String::new();
+        match command {
+            "b" | "break" => {
+                let address = parse_address(arg(&args, 0)?)?;
+                self.add_breakpoint(address);
+                writeln!(output, "Breakpoint at {:#06X}", address).unwrap();
+            }
+            "d" | "delete" => {
+                let address = parse_address(arg(&args, 0)?)?;
+                if !self.remove_breakpoint(address) {
+                    return Err(format!("No breakpoint at {:#06X}", address));
+                }
+            }
+            "bl" | "breakpoints" => {
+                for address in self.breakpoints() {
+                    writeln!(output, "{:#06X}", address).unwrap();
+                }
+            }
+            "s" | "step" => {
+                let steps = match args.first() {
+                    Some(steps) => parse_number(steps)?,
+                    None => 1,
+                };
+                self.resume(cpu, RunState::Stepping(steps));
+                return Ok((output, Control::Resume));
+            }
+            "c" | "continue" => {
+                self.resume(cpu, RunState::Running);
+                return Ok((output, Control::Resume));
+            }
+            "r" | "regs" => output.push_str(&headless::register_dump(cpu)),
+            "stack" => {
+                for (depth, address) in cpu.stack().iter().enumerate().rev() {
+                    writeln!(output, "{:2}: {:#06X}", depth, address).unwrap();
+                }
+            }
+            "m" | "mem" => {
+                let address = parse_address(arg(&args, 0)?)?;
+                let size = match args.get(1) {
+                    Some(size) => Some(parse_number(size)?)
+                        .filter(|&size| size <= MEMORY_SIZE as u64)
+                        .ok_or_else(|| format!("{} is more than the whole memory", size))?
+                        as usize,
+                    None => 64,
+                };
+                let data = cpu.memory().peek(address, size);
+                for (line, bytes) in data.chunks(16).enumerate() {
+                    write!(output, "{:04X}:", address as usize + line * 16).unwrap();
+                    for byte in bytes {
+                        write!(output, " {:02X}", byte).unwrap();
+                    }
+                    output.push('\n');
+                }
+            }
+            "set" => {
+                let target = arg(&args, 0)?.to_ascii_lowercase();
+                set(cpu, &target, arg(&args, 1)?)?;
+            }
+            "poke" => {
+                let address = parse_address(arg(&args, 0)?)?;
+                let bytes = args[1..]
+                    .iter()
+                    .map(|byte| parse_byte(byte))
+                    .collect::<Result<Vec<_>, _>>()?;
+                cpu.memory_mut()
+                    .write_data(address, &bytes)
+                    .map_err(|_| "Write goes past the end of memory".to_string())?;
+            }
+            "h" | "help" => writeln!(output, "{}", HELP).unwrap(),
+            "q" | "quit" => return Ok((output, Control::Quit)),
+            _ => return Err(format!("Unknown command '{}', try help", command)),
+        }
+        Ok((output, Control::Stay))
+    }
+
+    fn resume(&mut self, cpu: &Cpu, state: RunState) {
+        self.state = state;
+        self.resumed_at = Some(cpu.program_counter());
+    }
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str, String> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| "Missing argument, try help".to_string())
+}
+
+fn parse_address(value: &str) -> Result<u16, String> {
+    let address = parse_number(value)?;
+    if address > 0xFFFF {
+        return Err(format!("{} is not an address", value));
+    }
+    Ok(address as u16)
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let byte = parse_number(value)?;
+    if byte > 0xFF {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(byte as u8)
+}
+
+fn set(cpu: &mut Cpu, target: &str, value: &str) -> Result<(), String> {
+    match target {
+        "i" => cpu.set_i_reg(parse_address(value)?),
+        "pc" => cpu.set_program_counter(parse_address(value)?),
+        "dt" => cpu.set_delay_timer(parse_byte(value)?),
+        "st" => cpu.set_sound_timer(parse_byte(value)?),
+        _ => {
+            let register = target
+                .strip_prefix('v')
+                .and_then(|register| u8::from_str_radix(register, 16).ok())
+                .filter(|&register| register < 16)
+                .ok_or_else(|| format!("Can't set '{}'", target))?;
+            cpu.set_register(register, parse_byte(value)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_dumps_stop_at_the_end_of_memory() {
+        let mut debugger = Debugger::new();
+        let mut cpu = Cpu::new();
+        let (output, _) = debugger.execute(&mut cpu, "m 0xFFF8 0x100").unwrap();
+        assert_eq!(output, "FFF8: 00 00 00 00 00 00 00 00\n");
+        assert_eq!(
+            debugger.execute(&mut cpu, "m 0x10 0xFFFFFFFFFFFFFFFF"),
+            Err("0xFFFFFFFFFFFFFFFF is more than the whole memory".to_string())
+        );
+        assert_eq!(cpu.memory().peek(0xFFFF, usize::MAX).len(), 1);
+    }
+}
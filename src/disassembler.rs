@@ -0,0 +1,184 @@
+use crate::opcode::Opcode;
+
+use std::fmt;
+
+// At most this many data bytes go on one line
+const DATA_BYTES_PER_LINE: usize = 8;
+
+/// One line of disassembly: an instruction or a run of data bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>();
+        write!(f, "{:04X}: {:16} {}", self.address, hex, self.text)
+    }
+}
+
+/// Disassembles every word in `rom`, which is loaded at `origin`, as an instruction
+pub fn disassemble(rom: &[u8], origin: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        match instruction_at(rom, offset, origin) {
+            Some(line) => {
+                offset += line.bytes.len();
+                lines.push(line);
+            }
+            None => {
+                lines.push(data_line(rom, offset, offset + 1, origin));
+                offset += 1;
+            }
+        }
+    }
+    lines
+}
+
+/// Follows control flow from the start of `rom` and marks each offset a reachable
+/// instruction starts at. Indirect jumps can't be followed, so their targets stay data.
+pub fn find_code(rom: &[u8], origin: u16) -> Vec<bool> {
+    let mut starts = vec![false; rom.len()];
+    let mut pending = vec![0usize];
+    while let Some(mut offset) = pending.pop() {
+        while offset + 1 < rom.len() && !starts[offset] {
+            let opcode = Opcode::from(word_at(rom, offset));
+            let size = instruction_size(opcode);
+            if offset + size > rom.len() {
+                break;
+            }
+            if let Opcode::Unknown { .. } = opcode {
+                break;
+            }
+            starts[offset] = true;
+            let next = offset + size;
+            match opcode {
+                Opcode::Goto { address } => {
+                    pending.extend(offset_of(address, origin));
+                    break;
+                }
+                Opcode::CallSubroutine { address } => pending.extend(offset_of(address, origin)),
+                Opcode::Return | Opcode::Exit | Opcode::JumpIndirect { .. } => break,
+                Opcode::IfRegEqual { .. }
+                | Opcode::IfRegNotEqual { .. }
+                | Opcode::IfRegsEqual { .. }
+                | Opcode::IfRegsNotEqual { .. }
+                | Opcode::IfKeyEqual { .. }
+                | Opcode::IfKeyNotEqual { .. }
+                    if next + 1 < rom.len() =>
+                {
+                    // Skips jump over a whole long load, like the CPU does
+                    let skipped = instruction_size(Opcode::from(word_at(rom, next)));
+                    pending.push(next + skipped);
+                }
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+    starts
+}
+
+/// Disassembles the code reachable from the start of `rom` and shows everything else as data.
+/// Where reachable instructions overlap, only the first one is shown.
+pub fn disassemble_code(rom: &[u8], origin: u16) -> Vec<Line> {
+    let starts = find_code(rom, origin);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        if starts[offset] {
+            if let Some(line) = instruction_at(rom, offset, origin) {
+                offset += line.bytes.len();
+                lines.push(line);
+                continue;
+            }
+        }
+        let mut end = offset + 1;
+        while end < rom.len() && !starts[end] && end - offset < DATA_BYTES_PER_LINE {
+            end += 1;
+        }
+        lines.push(data_line(rom, offset, end, origin));
+        offset = end;
+    }
+    lines
+}
+
+fn word_at(rom: &[u8], offset: usize) -> u16 {
+    (rom[offset] as u16) << 8 | rom[offset + 1] as u16
+}
+
+fn instruction_size(opcode: Opcode) -> usize {
+    match opcode {
+        Opcode::SetILong => 4,
+        _ => 2,
+    }
+}
+
+fn offset_of(address: u16, origin: u16) -> Option<usize> {
+    address.checked_sub(origin).map(|offset| offset as usize)
+}
+
+fn instruction_at(rom: &[u8], offset: usize, origin: u16) -> Option<Line> {
+    if offset + 1 >= rom.len() {
+        return None;
+    }
+    let opcode = Opcode::from(word_at(rom, offset));
+    let size = instruction_size(opcode);
+    if offset + size > rom.len() {
+        return None;
+    }
+    let text = match opcode {
+        Opcode::SetILong => format!("LD I, LONG {:#06X}", word_at(rom, offset + 2)),
+        _ => opcode.to_string(),
+    };
+    Some(Line {
+        address: origin.wrapping_add(offset as u16),
+        bytes: rom[offset..offset + size].to_vec(),
+        text,
+    })
+}
+
+fn data_line(rom: &[u8], start: usize, end: usize, origin: u16) -> Line {
+    let bytes = rom[start..end].to_vec();
+    let values = bytes
+        .iter()
+        .map(|byte| format!("{:#04X}", byte))
+        .collect::<Vec<_>>();
+    Line {
+        address: origin.wrapping_add(start as u16),
+        text: format!("DB {}", values.join(", ")),
+        bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_instructions_do_not_panic() {
+        // The jumps land on odd offsets, so the instruction at 0x203 overlaps the one at 0x202
+        let rom = [0x12, 0x03, 0x00, 0x12, 0x02];
+        let lines = disassemble_code(&rom, 0x200);
+        let covered = lines.iter().map(|line| line.bytes.len()).sum::<usize>();
+        assert_eq!(covered, rom.len());
+        assert_eq!(lines[0].text, "JP 0x203");
+    }
+
+    #[test]
+    fn unreachable_bytes_are_data() {
+        let rom = [0x00, 0xE0, 0x00, 0xFD, 0xAB, 0xCD];
+        let lines = disassemble_code(&rom, 0x200);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2].address, 0x204);
+        assert_eq!(lines[2].text, "DB 0xAB, 0xCD");
+    }
+}
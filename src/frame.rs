@@ -0,0 +1,88 @@
+use crate::cpu::{Cpu, EmulationError, StepOutcome};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the timers tick and the screen is drawn, in Hz
+pub const FRAME_RATE: u32 = 60;
+
+// Roughly the 500Hz the emulator has always run at
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 8;
+
+/// Converts a clock speed in Hz to whole instructions per frame, at least one
+pub fn instructions_per_frame(clock: u32) -> u32 {
+    (clock.saturating_add(FRAME_RATE / 2) / FRAME_RATE).max(1)
+}
+
+/// How a frame run with `run_frame` ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameOutcome {
+    /// Every instruction ran and the timers ticked
+    Completed,
+    /// The program exited partway through
+    Exited,
+    /// `before_instruction` stopped the frame partway through
+    Stopped,
+}
+
+/// Runs one frame of `instructions` then ticks the timers, the way every frontend does.
+/// `before_instruction` is called ahead of each instruction and returning false from it
+/// stops the frame there, without ticking the timers.
+pub fn run_frame<F: FnMut(&mut Cpu) -> bool>(
+    cpu: &mut Cpu,
+    instructions: u32,
+    mut before_instruction: F,
+) -> Result<FrameOutcome, EmulationError> {
+    for _ in 0..instructions {
+        if !before_instruction(cpu) {
+            return Ok(FrameOutcome::Stopped);
+        }
+        if cpu.emulate_cycle()? == StepOutcome::Exited {
+            return Ok(FrameOutcome::Exited);
+        }
+    }
+    cpu.tick_timers();
+    Ok(FrameOutcome::Completed)
+}
+
+/// Paces a frontend's main loop to the frame rate. Sleeps are measured against a schedule
+/// rather than from the end of the last frame, so time spent emulating and drawing doesn't
+/// make the loop drift.
+pub struct FrameLimiter {
+    frame_time: Duration,
+    next_frame: Instant,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self::new(FRAME_RATE)
+    }
+}
+
+impl FrameLimiter {
+    pub fn new(frames_per_second: u32) -> Self {
+        FrameLimiter {
+            frame_time: Duration::from_secs(1) / frames_per_second,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Sleeps until the next frame is due. A `slowdown` above 1 stretches the frame for slow
+    /// motion.
+    pub fn wait(&mut self, slowdown: u32) {
+        let frame_time = self.frame_time * slowdown;
+        self.next_frame += frame_time;
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > frame_time {
+            // Too far behind to catch up, say after being paused, so start over from now
+            self.next_frame = now;
+        }
+    }
+
+    /// Restarts the schedule from now, for after frames have run without waiting
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now();
+    }
+}
@@ -0,0 +1,296 @@
+use crate::frame;
+use crate::palette::Palette;
+use crate::screen::{Screen, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Pixels are 2 bit palette indices, the smallest code size GIF allows
+const MIN_CODE_SIZE: u8 = 2;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+const MAX_CODE: u16 = 4095;
+
+// A distinct screen and how many frames it stayed up for
+struct Frame {
+    width: u16,
+    height: u16,
+    pixels: Vec<u8>,
+    frames: u32,
+}
+
+/// Collects the screen once per frame and encodes it as a looping animated GIF. A screen
+/// that didn't change since the last frame just makes the last one last longer.
+pub struct GifRecorder {
+    pub scale: u32,
+    pub palette: Palette,
+    frames: Vec<Frame>,
+}
+
+impl GifRecorder {
+    pub fn new(scale: u32, palette: Palette) -> Self {
+        GifRecorder {
+            scale,
+            palette,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds the screen as it is at the end of a frame
+    pub fn capture(&mut self, screen: &Screen) {
+        let pixels = screen.get_pixel_data();
+        if let Some(last) = self.frames.last_mut() {
+            if last.width == screen.width() && last.pixels[..] == pixels[..] {
+                last.frames += 1;
+                return;
+            }
+        }
+        self.frames.push(Frame {
+            width: screen.width(),
+            height: screen.height(),
+            pixels: pixels.to_vec(),
+            frames: 1,
+        });
+    }
+
+    /// The number of frames captured, counting repeats
+    pub fn frame_count(&self) -> u32 {
+        self.frames.iter().map(|frame| frame.frames).sum()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // Lores frames are drawn twice the size if the recording switches to hires at all
+        let hires = self.frames.iter().any(|frame| frame.width > LORES_WIDTH);
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        };
+        let width = (width as u32 * self.scale).min(u16::MAX as u32) as u16;
+        let height = (height as u32 * self.scale).min(u16::MAX as u32) as u16;
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        // A global colour table of 4 entries, then the background colour and aspect ratio
+        gif.extend_from_slice(&[0b1000_0001, 0, 0]);
+        for color in self.palette.colors.iter() {
+            gif.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+        // The Netscape extension, looping forever
+        gif.extend_from_slice(&[0x21, 0xFF, 11]);
+        gif.extend_from_slice(b"NETSCAPE2.0");
+        gif.extend_from_slice(&[3, 1, 0, 0, 0]);
+
+        // Delays are in hundredths of a second, so round the running time rather than each
+        // frame to keep the total right
+        let mut elapsed = 0;
+        let centiseconds = |frames: u32| (frames * 100 + frame::FRAME_RATE / 2) / frame::FRAME_RATE;
+        for frame in &self.frames {
+            let delay = centiseconds(elapsed + frame.frames) - centiseconds(elapsed);
+            elapsed += frame.frames;
+            let delay = delay.min(u16::MAX as u32) as u16;
+            gif.extend_from_slice(&[0x21, 0xF9, 4, 0]);
+            gif.extend_from_slice(&delay.to_le_bytes());
+            gif.extend_from_slice(&[0, 0]);
+
+            gif.push(0x2C);
+            gif.extend_from_slice(&[0, 0, 0, 0]);
+            gif.extend_from_slice(&width.to_le_bytes());
+            gif.extend_from_slice(&height.to_le_bytes());
+            gif.push(0);
+            gif.push(MIN_CODE_SIZE);
+            let pixels = frame.scaled(width, height);
+            for block in lzw_encode(&pixels).chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend_from_slice(block);
+            }
+            gif.push(0);
+        }
+        gif.push(0x3B);
+        gif
+    }
+}
+
+impl Frame {
+    // Stretches the frame to fill the image
+    fn scaled(&self, width: u16, height: u16) -> Vec<u8> {
+        let scale_x = width as usize / self.width as usize;
+        let scale_y = height as usize / self.height as usize;
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height as usize {
+            let row = (y / scale_y).min(self.height as usize - 1) * self.width as usize;
+            for x in 0..width as usize {
+                let column = (x / scale_x).min(self.width as usize - 1);
+                pixels.push(self.pixels[row + column] & 0b11);
+            }
+        }
+        pixels
+    }
+}
+
+// Packs codes into bytes starting from the least significant bit, as GIF wants
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = MIN_CODE_SIZE + 1;
+    let mut next = END_CODE + 1;
+    writer.write(CLEAR_CODE, size);
+
+    let mut pixels = pixels.iter().copied();
+    let mut prefix = match pixels.next() {
+        Some(pixel) => pixel as u16,
+        None => {
+            writer.write(END_CODE, size);
+            return writer.finish();
+        }
+    };
+    for pixel in pixels {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, size);
+        if next <= MAX_CODE {
+            table.insert((prefix, pixel), next);
+            // The decoder widens its codes one code later than it adds to its table, which
+            // lines up with widening here as soon as a code needs the extra bit
+            if next == 1 << size && size < 12 {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            // The table is full, so start over
+            writer.write(CLEAR_CODE, size);
+            table.clear();
+            size = MIN_CODE_SIZE + 1;
+            next = END_CODE + 1;
+        }
+        prefix = pixel as u16;
+    }
+    writer.write(prefix, size);
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    writer.write(END_CODE, size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    // A plain GIF LZW decoder, returning the pixels and how many clear codes it read
+    fn lzw_decode(data: &[u8]) -> (Vec<u8>, usize) {
+        let reset = || {
+            (0..=END_CODE)
+                .map(|code| vec![code as u8])
+                .collect::<Vec<_>>()
+        };
+        let mut table = reset();
+        let mut size = MIN_CODE_SIZE + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut pixels = Vec::new();
+        let mut clears = 0;
+        let (mut buffer, mut bits, mut bytes) = (0u32, 0, data.iter());
+        loop {
+            while bits < size {
+                buffer |= (*bytes.next().expect("no end code") as u32) << bits;
+                bits += 8;
+            }
+            let code = (buffer & ((1 << size) - 1)) as u16;
+            buffer >>= size;
+            bits -= size;
+
+            if code == CLEAR_CODE {
+                table = reset();
+                size = MIN_CODE_SIZE + 1;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == END_CODE {
+                break;
+            }
+            let entry = match table.get(code as usize) {
+                Some(entry) => entry.clone(),
+                None => {
+                    assert_eq!(code as usize, table.len(), "code from the future");
+                    let mut entry = previous.clone().expect("first code isn't a pixel");
+                    entry.push(entry[0]);
+                    entry
+                }
+            };
+            if let Some(mut added) = previous.take() {
+                if table.len() <= MAX_CODE as usize {
+                    added.push(entry[0]);
+                    table.push(added);
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            pixels.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+        (pixels, clears)
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        let mut random = Random::new(1);
+        let noise = (0..100_000)
+            .map(|_| random.next_u8() & 0b11)
+            .collect::<Vec<_>>();
+        let runs = (0..50_000).map(|i| (i / 37 % 4) as u8).collect::<Vec<_>>();
+        for pixels in [vec![], vec![3], vec![0; 10_000], runs, noise].iter() {
+            let (decoded, _) = lzw_decode(&lzw_encode(pixels));
+            assert_eq!(&decoded, pixels);
+        }
+    }
+
+    #[test]
+    fn lzw_starts_over_when_the_table_fills() {
+        let mut random = Random::new(2);
+        let noise = (0..100_000)
+            .map(|_| random.next_u8() & 0b11)
+            .collect::<Vec<_>>();
+        // One clear to start with, then one each time the 4096 codes run out
+        let (decoded, clears) = lzw_decode(&lzw_encode(&noise));
+        assert_eq!(decoded, noise);
+        assert!(clears > 2, "only {} clear codes", clears);
+    }
+}
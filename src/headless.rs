@@ -0,0 +1,163 @@
+use crate::cpu::{Cpu, EmulationError, StepOutcome};
+use crate::frame;
+use crate::screen::Screen;
+
+use std::fmt::Write;
+
+pub const DEFAULT_CYCLES_PER_FRAME: u64 = frame::DEFAULT_INSTRUCTIONS_PER_FRAME as u64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunLength {
+    Cycles(u64),
+    Frames(u64),
+}
+
+/// A key going down or up at a given step, which is a frame or a cycle depending on how the
+/// run is measured
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub step: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+pub struct Runner {
+    pub length: RunLength,
+    pub cycles_per_frame: u64,
+    pub key_events: Vec<KeyEvent>,
+}
+
+impl Runner {
+    pub fn new(length: RunLength) -> Self {
+        Runner {
+            length,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            key_events: Vec::new(),
+        }
+    }
+
+    /// Runs the CPU for the configured length, returning the number of cycles executed. Stops
+    /// early if the program exits. The timers tick once every `cycles_per_frame` cycles, also
+    /// when the length is counted in cycles.
+    pub fn run(&self, cpu: &mut Cpu) -> Result<u64, EmulationError> {
+        self.run_with(cpu, |_, _| {})
+    }
+
+    /// Like `run`, calling `after_step` with each step number once the step is done
+    pub fn run_with<F: FnMut(u64, &Cpu)>(
+        &self,
+        cpu: &mut Cpu,
+        mut after_step: F,
+    ) -> Result<u64, EmulationError> {
+        let (steps, cycles_per_step) = match self.length {
+            RunLength::Cycles(cycles) => (cycles, 1),
+            RunLength::Frames(frames) => (frames, self.cycles_per_frame),
+        };
+        // In step order, keeping events for the same step in the order they were given
+        let mut key_events = self.key_events.clone();
+        key_events.sort_by_key(|event| event.step);
+        let mut key_events = key_events.iter().peekable();
+        let mut cycles = 0;
+        let mut frame_cycles = 0;
+        for step in 0..steps {
+            while let Some(event) = key_events.next_if(|event| event.step <= step) {
+                if event.pressed {
+                    cpu.press_key(event.key);
+                } else {
+                    cpu.release_key(event.key);
+                }
+            }
+            for _ in 0..cycles_per_step {
+                let outcome = cpu.emulate_cycle()?;
+                cycles += 1;
+                if outcome == StepOutcome::Exited {
+                    // The step still happened, and whatever it drew should be seen
+                    after_step(step, cpu);
+                    return Ok(cycles);
+                }
+                frame_cycles += 1;
+                if frame_cycles >= self.cycles_per_frame {
+                    frame_cycles = 0;
+                    cpu.tick_timers();
+                }
+            }
+            after_step(step, cpu);
+        }
+        Ok(cycles)
+    }
+}
+
+/// One character per pixel, `.` for off and `#`, `+` or `@` for the XO-CHIP plane colours
+pub fn framebuffer_ascii(screen: &Screen) -> String {
+    const CHARACTERS: [char; 4] = ['.', '#', '+', '@'];
+    let mut ascii = String::new();
+    for row in screen.get_pixel_data().chunks(screen.width() as usize) {
+        ascii.extend(row.iter().map(|&pixel| CHARACTERS[pixel as usize]));
+        ascii.push('\n');
+    }
+    ascii
+}
+
+/// A plain (P1) PBM image, with a pixel set if it is on in any plane. Each comment line is
+/// written into the header.
+pub fn framebuffer_pbm(screen: &Screen, comments: &str) -> String {
+    let mut pbm = String::from("P1\n");
+    for line in comments.lines() {
+        writeln!(pbm, "# {}", line).unwrap();
+    }
+    writeln!(pbm, "{} {}", screen.width(), screen.height()).unwrap();
+    for row in screen.get_pixel_data().chunks(screen.width() as usize) {
+        let row = row
+            .iter()
+            .map(|&pixel| if pixel != 0 { "1" } else { "0" })
+            .collect::<Vec<_>>();
+        pbm.push_str(&row.join(" "));
+        pbm.push('\n');
+    }
+    pbm
+}
+
+pub fn register_dump(cpu: &Cpu) -> String {
+    let mut dump = String::new();
+    for (index, value) in cpu.registers().iter().enumerate() {
+        let separator = if index % 8 == 7 { '\n' } else { ' ' };
+        write!(dump, "V{:X}={:02X}{}", index, value, separator).unwrap();
+    }
+    writeln!(
+        dump,
+        "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+        cpu.i_reg(),
+        cpu.program_counter(),
+        cpu.stack().len(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    )
+    .unwrap();
+    let stack = cpu
+        .stack()
+        .iter()
+        .map(|address| format!("{:04X}", address))
+        .collect::<Vec<_>>();
+    writeln!(dump, "Stack=[{}]", stack.join(" ")).unwrap();
+    dump
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is stable between builds
+pub fn fnv1a(data: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    data.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+pub fn memory_hash(cpu: &Cpu) -> u64 {
+    fnv1a(cpu.memory().as_slice())
+}
+
+/// Covers the resolution as well as the pixels, so a blank lores and hires screen differ
+pub fn framebuffer_hash(screen: &Screen) -> u64 {
+    let mut data = screen.width().to_be_bytes().to_vec();
+    data.extend_from_slice(screen.get_pixel_data());
+    fnv1a(&data)
+}
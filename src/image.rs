@@ -0,0 +1,241 @@
+use crate::palette::Palette;
+use crate::screen::Screen;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Picks the format from a `.png` or `.ppm` extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match &extension[..] {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// An RGB image, three bytes per pixel, row by row from the top left
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Draws the framebuffer in the palette's colours, each pixel as a `scale` sized square
+    pub fn from_screen(screen: &Screen, scale: u32, palette: &Palette) -> Image {
+        let width = screen.width() as u32 * scale;
+        let height = screen.height() as u32 * scale;
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for row in screen.get_pixel_data().chunks(screen.width() as usize) {
+            let mut line = Vec::with_capacity(width as usize * 3);
+            for &pixel in row {
+                let color = palette.color(pixel);
+                for _ in 0..scale {
+                    line.extend_from_slice(&[color.r, color.g, color.b]);
+                }
+            }
+            for _ in 0..scale {
+                data.extend_from_slice(&line);
+            }
+        }
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Png => self.to_png(),
+            ImageFormat::Ppm => self.to_ppm(),
+        }
+    }
+
+    /// Writes the image in the format its extension names
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the file name should end in .png or .ppm",
+            )
+        })?;
+        fs::write(path, self.encode(format))
+    }
+
+    /// A binary (P6) PPM
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        ppm.extend_from_slice(&self.data);
+        ppm
+    }
+
+    /// A truecolour PNG. The pixels aren't compressed, the zlib stream is made of stored
+    /// deflate blocks, which keeps the encoder small.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel, RGB, then the default compression, filter and no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+
+        // Every row starts with its filter type, 0 for none
+        let mut raw = Vec::with_capacity(self.data.len() + self.height as usize);
+        for row in self.data.chunks(self.width as usize * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of uncompressed deflate blocks, which hold up to 65535 bytes each
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // An empty stream still needs its final block
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        zlib.push(last as u8);
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+// The CRC-32 used by PNG and zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    // Reads stored blocks back out of a zlib stream, checking the framing on the way
+    fn unzlib_stored(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(&zlib[..2], &[0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let mut data = Vec::new();
+        let mut offset = 2;
+        loop {
+            let header = zlib[offset];
+            let length = u16::from_le_bytes([zlib[offset + 1], zlib[offset + 2]]);
+            let inverse = u16::from_le_bytes([zlib[offset + 3], zlib[offset + 4]]);
+            assert_eq!(header & !1, 0, "not a stored block");
+            assert_eq!(length, !inverse);
+            offset += 5;
+            data.extend_from_slice(&zlib[offset..offset + length as usize]);
+            offset += length as usize;
+            if header & 1 == 1 {
+                break;
+            }
+        }
+        let adler = u32::from_be_bytes([
+            zlib[offset],
+            zlib[offset + 1],
+            zlib[offset + 2],
+            zlib[offset + 3],
+        ]);
+        assert_eq!(adler, adler32(&data));
+        assert_eq!(offset + 4, zlib.len());
+        data
+    }
+
+    #[test]
+    fn stored_zlib_round_trips_across_blocks() {
+        for &len in [0, 1, 0xFFFF, 0x10000, 200_000].iter() {
+            let data = (0..len).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+            assert_eq!(unzlib_stored(&zlib_stored(&data)), data);
+        }
+    }
+
+    #[test]
+    fn png_chunks_are_well_formed() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            data: vec![255, 0, 0, 0, 0, 255],
+        };
+        let png = image.to_png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut offset = 8;
+        let mut kinds = Vec::new();
+        while offset < png.len() {
+            let length = u32::from_be_bytes([
+                png[offset],
+                png[offset + 1],
+                png[offset + 2],
+                png[offset + 3],
+            ]) as usize;
+            let chunk = &png[offset + 4..offset + 8 + length];
+            let crc = &png[offset + 8 + length..offset + 12 + length];
+            assert_eq!(crc, &crc32(chunk).to_be_bytes());
+            if &chunk[..4] == b"IDAT" {
+                assert_eq!(unzlib_stored(&chunk[4..]), [0, 255, 0, 0, 0, 0, 255]);
+            }
+            kinds.push(chunk[..4].to_vec());
+            offset += 12 + length;
+        }
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+    }
+}
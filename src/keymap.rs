@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The hex keys in the order they sit on the keypad, row by row:
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// The host keys the emulator keeps for itself, which can't be bound to the keypad
+pub const HOTKEYS: [&str; 13] = [
+    "Escape",
+    "Backspace",
+    "Tab",
+    "`",
+    "M",
+    "P",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F12",
+];
+
+// The host keys that fall under the keypad layout, i.e. the block under 1234, on each
+// keyboard layout. Names are the ones SDL gives the keys. Dvorak and Colemak have P where
+// QWERTY has R, which is the pause hotkey, so they take the key to its right instead.
+const PRESETS: [(&str, [&str; 16]); 5] = [
+    (
+        "qwerty",
+        [
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+        ],
+    ),
+    (
+        "azerty",
+        [
+            "1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+        ],
+    ),
+    (
+        "qwertz",
+        [
+            "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V",
+        ],
+    ),
+    (
+        "dvorak",
+        [
+            "1", "2", "3", "4", "'", ",", ".", "Y", "A", "O", "E", "U", ";", "Q", "J", "K",
+        ],
+    ),
+    (
+        "colemak",
+        [
+            "1", "2", "3", "4", "Q", "W", "F", "G", "A", "R", "S", "T", "Z", "X", "C", "V",
+        ],
+    ),
+];
+
+/// Whether `host_key` is one of the `HOTKEYS`
+pub fn is_hotkey(host_key: &str) -> bool {
+    HOTKEYS
+        .iter()
+        .any(|hotkey| hotkey.eq_ignore_ascii_case(host_key))
+}
+
+/// Which host key presses which hex key. Host keys are identified by name, compared without
+/// case, so the map doesn't depend on any particular frontend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: HashMap<String, u8>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::preset("qwerty").unwrap()
+    }
+}
+
+impl KeyMap {
+    pub fn empty() -> Self {
+        KeyMap {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn preset_names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|(name, _)| *name)
+    }
+
+    pub fn preset(name: &str) -> Option<KeyMap> {
+        let (_, host_keys) = PRESETS.iter().find(|(preset, _)| *preset == name)?;
+        let mut keymap = KeyMap::empty();
+        for (host_key, &key) in host_keys.iter().zip(KEYPAD_LAYOUT.iter()) {
+            keymap.bind(host_key, key);
+        }
+        Some(keymap)
+    }
+
+    /// A preset name, or a file of `<hex key> = <host key>` lines moving keys around the
+    /// QWERTY preset
+    pub fn load(name_or_path: &Path) -> Result<KeyMap, String> {
+        if let Some(keymap) = name_or_path.to_str().and_then(KeyMap::preset) {
+            return Ok(keymap);
+        }
+        let text = fs::read_to_string(name_or_path)
+            .map_err(|e| format!("Could not read keymap {}: {}", name_or_path.display(), e))?;
+        let mut keymap = KeyMap::default();
+        keymap
+            .apply(&text)
+            .map_err(|e| format!("{}: {}", name_or_path.display(), e))?;
+        Ok(keymap)
+    }
+
+    /// Applies `<hex key> = <host key>` lines, with `#` starting a comment. Keys that aren't
+    /// mentioned stay where they are.
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let host_key = parts
+                .next()
+                .map(str::trim)
+                .filter(|host_key| !host_key.is_empty())
+                .ok_or_else(|| error("expected <hex key> = <host key>".to_string()))?;
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| error(format!("'{}' is not a hex key", key)))?;
+            if is_hotkey(host_key) {
+                return Err(error(format!("'{}' is a hotkey", host_key)));
+            }
+            self.bind(host_key, key);
+        }
+        Ok(())
+    }
+
+    /// Makes `host_key` the only key for `key`, replacing whatever else either was bound to
+    pub fn bind(&mut self, host_key: &str, key: u8) {
+        assert!(key < 16);
+        self.bindings.retain(|_, &mut bound| bound != key);
+        self.bindings.insert(host_key.to_lowercase(), key);
+    }
+
+    pub fn key_for(&self, host_key: &str) -> Option<u8> {
+        self.bindings.get(&host_key.to_lowercase()).copied()
+    }
+
+    pub fn host_key_for(&self, key: u8) -> Option<&str> {
+        self.bindings
+            .iter()
+            .find(|(_, &bound)| bound == key)
+            .map(|(host_key, _)| &host_key[..])
+    }
+}
+
+/// Writes the map in the format `apply` reads
+impl fmt::Display for KeyMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &key in KEYPAD_LAYOUT.iter() {
+            if let Some(host_key) = self.host_key_for(key) {
+                writeln!(f, "{:X} = {}", key, host_key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_leave_the_hotkeys_alone() {
+        for name in KeyMap::preset_names() {
+            let keymap = KeyMap::preset(name).unwrap();
+            for &key in KEYPAD_LAYOUT.iter() {
+                let host_key = keymap.host_key_for(key).unwrap();
+                assert!(!is_hotkey(host_key), "{} binds {}", name, host_key);
+            }
+        }
+    }
+
+    #[test]
+    fn hotkeys_cannot_be_bound() {
+        let mut keymap = KeyMap::default();
+        assert_eq!(
+            keymap.apply("D = p"),
+            Err("line 1: 'p' is a hotkey".to_string())
+        );
+        assert_eq!(
+            keymap.apply("1 = F1"),
+            Err("line 1: 'F1' is a hotkey".to_string())
+        );
+        assert_eq!(keymap.apply("# comment\nD = K"), Ok(()));
+        assert_eq!(keymap.key_for("k"), Some(0xD));
+    }
+}
@@ -1,6 +1,25 @@
+pub mod assembler;
+pub mod audio;
+pub mod config;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
 pub mod font;
+pub mod frame;
+pub mod gif;
+pub mod headless;
+pub mod image;
+pub mod keymap;
 pub mod keypad;
 pub mod memory;
+pub mod movie;
 pub mod opcode;
+pub mod palette;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
 pub mod screen;
+pub mod sha1;
+pub mod state;
+pub mod trace;
+pub mod util;
@@ -1,44 +1,439 @@
+use chip8_emu::audio::{Tone, ToneGenerator, Waveform};
+use chip8_emu::config::{Config, Settings};
 use chip8_emu::cpu::Cpu;
-use chip8_emu::screen;
+use chip8_emu::debugger::{self, Control, Debugger};
+use chip8_emu::font;
+use chip8_emu::frame::{self, FrameLimiter, FrameOutcome};
+use chip8_emu::gif::GifRecorder;
+use chip8_emu::headless::{self, RunLength, Runner};
+use chip8_emu::image::Image;
+use chip8_emu::keymap::{self, KeyMap, KEYPAD_LAYOUT};
+use chip8_emu::movie::{self, Movie};
+use chip8_emu::palette::{Palette, Rgb};
+use chip8_emu::quirks::Preset;
+use chip8_emu::rewind::RewindBuffer;
+use chip8_emu::screen::{Screen, LORES_HEIGHT, LORES_WIDTH};
+use chip8_emu::trace::{self, Tracer};
+use chip8_emu::util::{parse_count, parse_number};
 
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
-use std::time::Duration;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
 
-const WINDOW_WIDTH: usize = 640;
-const WINDOW_HEIGHT: usize = 320;
+// Window pixels per CHIP-8 pixel in lores mode
+const DEFAULT_SCALE: u32 = 10;
+const MAX_SCALE: u32 = 64;
 
-// The size of one screen pixel in the window
-const SCREEN_PIXEL_WIDTH_ON_WINDOW: f32 = WINDOW_WIDTH as f32 / screen::WIDTH as f32;
-const SCREEN_PIXEL_HEIGHT_ON_WINDOW: f32 = WINDOW_HEIGHT as f32 / screen::HEIGHT as f32;
+// How many times longer each frame lasts while the slow motion key is held
+const SLOW_MOTION_FACTOR: u32 = 4;
+const DEFAULT_REWIND_SECONDS: usize = 10;
+// An hour, already more snapshots than anyone will hold backspace through
+const MAX_REWIND_SECONDS: u64 = 3600;
+
+const TITLE: &str = "Chip8 Emulator";
+
+// The rebinding screen shows the keypad as a 4x4 grid of hex digits, with the one waiting
+// for a host key highlighted
+fn draw_rebinding(canvas: &mut WindowCanvas, palette: &Palette, index: usize) {
+    const CELL_WIDTH: u16 = LORES_WIDTH / 4;
+    const CELL_HEIGHT: u16 = LORES_HEIGHT / 4;
+    let mut screen = Screen::default();
+    for (position, &key) in KEYPAD_LAYOUT.iter().enumerate() {
+        let cell_x = position as u16 % 4 * CELL_WIDTH;
+        let cell_y = position as u16 / 4 * CELL_HEIGHT;
+        let glyph = &font::FONT_SET[key as usize * 5..][..5];
+        screen.draw_sprite(cell_x + (CELL_WIDTH - 4) / 2, cell_y + 1, glyph, false);
+        if position == index {
+            for y in cell_y..cell_y + CELL_HEIGHT {
+                for x in cell_x..cell_x + CELL_WIDTH {
+                    screen.toggle_pixel(x, y, 2);
+                }
+            }
+        }
+    }
+    draw(canvas, &screen, palette);
+    let title = format!(
+        "{} - press the key for {:X}, escape cancels",
+        TITLE, KEYPAD_LAYOUT[index]
+    );
+    canvas.window_mut().set_title(&title).unwrap();
+}
+
+// Save state slots: F1-F4 load, Shift+F1-F4 save
+fn keycode_to_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+// States are kept next to the ROM, e.g. pong.ch8.state1
+fn state_path(rom: &str, slot: u8) -> String {
+    format!("{}.state{}", rom, slot)
+}
+
+fn save_state(cpu: &Cpu, rom: &str, slot: u8) {
+    let path = state_path(rom, slot);
+    match fs::write(&path, cpu.save_state()) {
+        Ok(()) => println!("Saved state to {}", path),
+        Err(e) => eprintln!("Could not save state to {}: {}", path, e),
+    }
+}
+
+fn load_state(cpu: &mut Cpu, rom: &str, slot: u8) -> bool {
+    let path = state_path(rom, slot);
+    let result = fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| cpu.load_state(&data).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => {
+            println!("Loaded state from {}", path);
+            true
+        }
+        Err(e) => {
+            eprintln!("Could not load state from {}: {}", path, e);
+            false
+        }
+    }
+}
+
+// Screenshots and recordings go next to the ROM as well, numbered so none get overwritten,
+// e.g. pong.ch8.screenshot1.png
+fn numbered_path(rom: &str, kind: &str, extension: &str) -> String {
+    (1..)
+        .map(|number| format!("{}.{}{}.{}", rom, kind, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn save_screenshot(screen: &Screen, rom: &str, scale: u32, palette: &Palette) {
+    let path = numbered_path(rom, "screenshot", "png");
+    // The size of the window, so hires pixels are half the size of lores ones
+    let scale = (scale * LORES_WIDTH as u32 / screen.width() as u32).max(1);
+    match Image::from_screen(screen, scale, palette).save(Path::new(&path)) {
+        Ok(()) => println!("Saved screenshot to {}", path),
+        Err(e) => eprintln!("Could not save screenshot to {}: {}", path, e),
+    }
+}
+
+fn save_gif(gif: &GifRecorder, rom: &str) {
+    let path = numbered_path(rom, "recording", "gif");
+    match gif.save(Path::new(&path)) {
+        Ok(()) => println!("Saved {} frames to {}", gif.frame_count(), path),
+        Err(e) => eprintln!("Could not save GIF to {}: {}", path, e),
+    }
+}
+
+// Plays the tone whenever the main loop says the buzzer is on
+struct Buzzer {
+    generator: ToneGenerator,
+    sounding: bool,
+}
+
+impl AudioCallback for Buzzer {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        self.generator.fill(samples, self.sounding);
+    }
+}
+
+fn open_audio(audio: &sdl2::AudioSubsystem, tone: Tone) -> Result<AudioDevice<Buzzer>, String> {
+    let desired = AudioSpecDesired {
+        freq: Some(44_100),
+        channels: Some(1),
+        samples: None,
+    };
+    let device = audio.open_playback(None, &desired, |spec| Buzzer {
+        generator: ToneGenerator::new(tone, spec.freq as u32),
+        sounding: false,
+    })?;
+    device.resume();
+    Ok(device)
+}
+
+fn set_sounding(device: &mut Option<AudioDevice<Buzzer>>, sounding: bool) {
+    if let Some(device) = device {
+        device.lock().sounding = sounding;
+    }
+}
+
+// Reads debugger commands from stdin until one resumes execution. Returns false to quit.
+fn debug_prompt(debugger: &mut Debugger, cpu: &mut Cpu) -> bool {
+    let pc = cpu.program_counter();
+    match cpu.memory().get_u16(pc) {
+        Ok(opcode) => println!("{:#06X}: {:04X}", pc, opcode),
+        Err(_) => println!("{:#06X}: out of bounds", pc),
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            return false;
+        }
+        match debugger.execute(cpu, &line) {
+            Ok((output, control)) => {
+                print!("{}", output);
+                match control {
+                    Control::Stay => {}
+                    Control::Resume => return true,
+                    Control::Quit => return false,
+                }
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
 
 fn print_usage(name: &str) {
-    println!("Usage: {} <rom to load>", name);
+    println!("Usage: {} [options] <rom to load>", name);
+    println!();
+    println!("Options:");
+    println!(
+        "  --scale <n>           Window pixels per CHIP-8 pixel (default {})",
+        DEFAULT_SCALE
+    );
+    println!("  --fullscreen          Fill the screen instead of opening a window");
+    println!(
+        "  --ipf <n>             Instructions per frame (default {})",
+        frame::DEFAULT_INSTRUCTIONS_PER_FRAME
+    );
+    println!(
+        "  --clock <hz>          Instructions per second, rounded to whole frames (default {})",
+        frame::DEFAULT_INSTRUCTIONS_PER_FRAME * frame::FRAME_RATE
+    );
+    println!("  --quirks <preset>     Quirks preset to emulate");
+    println!("  --palette <palette>   Preset name, or 2 or 4 RRGGBB colours separated by commas");
+    println!("  --keymap <preset|file>");
+    println!("                        Keyboard layout, or a file of `<hex key> = <SDL key name>`");
+    println!("                        lines moving keys around the qwerty layout");
+    println!("  --paused              Start paused, P pauses and resumes");
+    println!("  --seed <n>            Seed the random number generator");
+    println!(
+        "  --rewind <seconds>    How far backspace can rewind (default {})",
+        DEFAULT_REWIND_SECONDS
+    );
+    println!("  --headless <frames>   Run without a window and print the final screen");
+    println!("  --debug               Start paused in a debugger reading commands from stdin");
+    println!("  --no-audio            Disable sound, M mutes it while running");
+    println!("  --tone <square|sine>  Buzzer waveform");
+    println!("  --frequency <hz>      Buzzer pitch (default 440)");
+    println!("  --volume <0-100>      Buzzer volume (default 25)");
+    println!("  --record <file>       Record the key presses to a movie file");
+    println!("  --play <file>         Play a movie back, checking for desyncs");
+    println!("  --trace <file>        Log every executed instruction to file");
+    println!("  --trace-addr <start>-<end>");
+    println!("                        Only log instructions in this address range");
+    println!("  --trace-cycles <start>-<end>");
+    println!("                        Only log instructions in this cycle range");
+    println!("  --config <file>       Read settings from file instead of the default config");
+    println!("  --no-config           Don't read any config file");
+    println!("  -h, --help            Print this help");
+    println!();
+    println!(
+        "Hold tab to fast-forward, ` for {}x slow motion and backspace to rewind.",
+        SLOW_MOTION_FACTOR
+    );
+    println!("F1-F4 load and Shift+F1-F4 save states. F5 saves a screenshot next to the ROM.");
+    println!("F6 starts and stops recording a GIF. F12 rebinds the keypad.");
+    if let Some(path) = Config::default_path() {
+        println!(
+            "Clock, quirks, palette and keymap defaults and per-ROM overrides are read from {}",
+            path.display()
+        );
+    }
+    let presets = Preset::ALL
+        .iter()
+        .map(|preset| preset.name())
+        .collect::<Vec<_>>();
+    println!("Quirks presets: {}", presets.join(", "));
+    let palettes = Palette::PRESETS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    println!("Palettes: {}", palettes.join(", "));
+    let keymaps = KeyMap::preset_names().collect::<Vec<_>>();
+    println!("Keymaps: {}", keymaps.join(", "));
+    println!();
+    println!("Debugger commands:");
+    println!("{}", debugger::HELP);
+}
+
+struct Options {
+    rom: String,
+    scale: u32,
+    fullscreen: bool,
+    // Whatever was given on the command line, which wins over the config
+    settings: Settings,
+    config: Option<PathBuf>,
+    no_config: bool,
+    paused: bool,
+    seed: Option<u64>,
+    rewind_seconds: usize,
+    headless: Option<u64>,
+    debug: bool,
+    audio: bool,
+    tone: Tone,
+    trace: Option<String>,
+    trace_addresses: Option<RangeInclusive<u16>>,
+    trace_cycles: Option<RangeInclusive<u64>>,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut scale = DEFAULT_SCALE;
+    let mut fullscreen = false;
+    let mut settings = Settings::default();
+    let mut config = None;
+    let mut no_config = false;
+    let mut paused = false;
+    let mut seed = None;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut headless = None;
+    let mut debug = false;
+    let mut audio = true;
+    let mut tone = Tone::default();
+    let mut trace = None;
+    let mut trace_addresses = None;
+    let mut trace_cycles = None;
+    let mut record = None;
+    let mut play = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match &arg[..] {
+            "--scale" => scale = parse_count(value()?, MAX_SCALE as u64)?,
+            "--fullscreen" => fullscreen = true,
+            "--ipf" => {
+                let max = (u32::MAX / frame::FRAME_RATE) as u64;
+                settings.clock = Some(parse_count(value()?, max)? * frame::FRAME_RATE);
+            }
+            "--clock" => settings.clock = Some(parse_count(value()?, u32::MAX as u64)?),
+            "--quirks" => {
+                let preset = value()?.parse::<Preset>().map_err(|e| e.to_string())?;
+                settings.quirks = Some(preset);
+            }
+            "--palette" => {
+                let palette = value()?.parse::<Palette>().map_err(|e| e.to_string())?;
+                settings.palette = Some(palette);
+            }
+            "--keymap" => settings.keymap = Some(PathBuf::from(value()?)),
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--no-config" => no_config = true,
+            "--paused" => paused = true,
+            "--seed" => seed = Some(parse_number(value()?)?),
+            "--rewind" => {
+                let value = value()?;
+                rewind_seconds = Some(parse_number(value)?)
+                    .filter(|&seconds| seconds <= MAX_REWIND_SECONDS)
+                    .ok_or_else(|| {
+                        format!("'{}' should be from 0 to {}", value, MAX_REWIND_SECONDS)
+                    })? as usize;
+            }
+            "--headless" => headless = Some(parse_number(value()?)?),
+            "--debug" => debug = true,
+            "--no-audio" => audio = false,
+            "--tone" => {
+                tone.waveform = value()?.parse::<Waveform>().map_err(|e| e.to_string())?;
+            }
+            "--frequency" => {
+                let frequency = value()?;
+                tone.frequency = frequency
+                    .parse()
+                    .ok()
+                    .filter(|&frequency: &f32| frequency.is_finite() && frequency > 0.0)
+                    .ok_or_else(|| format!("'{}' is not a frequency", frequency))?;
+            }
+            "--volume" => {
+                let volume = parse_number(value()?)?;
+                if volume > 100 {
+                    return Err(format!("'{}' is not a volume from 0 to 100", volume));
+                }
+                tone.volume = volume as f32 / 100.0;
+            }
+            "--trace" => trace = Some(value()?.clone()),
+            "--trace-addr" => trace_addresses = Some(trace::parse_address_range(value()?)?),
+            "--trace-cycles" => trace_cycles = Some(trace::parse_range(value()?)?),
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--play" => play = Some(PathBuf::from(value()?)),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom = rom.ok_or("no rom given")?;
+    if record.is_some() && headless.is_some() {
+        return Err("--record needs the window to take key presses from".to_string());
+    }
+    Ok(Options {
+        rom,
+        scale,
+        fullscreen,
+        settings,
+        config,
+        no_config,
+        paused,
+        seed,
+        rewind_seconds,
+        headless,
+        debug,
+        audio,
+        tone,
+        trace,
+        trace_addresses,
+        trace_cycles,
+        record,
+        play,
+    })
 }
 
-fn draw(canvas: &mut WindowCanvas, pixels: &[u8]) {
-    assert!(pixels.len() as u16 == screen::SIZE);
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+fn to_color(rgb: Rgb) -> Color {
+    Color::RGB(rgb.r, rgb.g, rgb.b)
+}
+
+fn draw(canvas: &mut WindowCanvas, screen: &Screen, palette: &Palette) {
+    let pixels = screen.get_pixel_data();
+    let width = screen.width();
+    let height = screen.height();
+
+    // The size of one screen pixel in the window, which shrinks in hires mode
+    let (window_width, window_height) = canvas.output_size().unwrap();
+    let pixel_width_on_window = window_width as f32 / width as f32;
+    let pixel_height_on_window = window_height as f32 / height as f32;
+
+    canvas.set_draw_color(to_color(palette.color(0)));
     canvas.clear();
 
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    for y in 0..screen::HEIGHT {
-        for x in 0..screen::WIDTH {
-            let index = x + (y * screen::WIDTH);
-            if pixels[index as usize] == 1 {
+    for y in 0..height {
+        for x in 0..width {
+            let index = x + (y * width);
+            let pixel = pixels[index as usize];
+            if pixel != 0 {
+                canvas.set_draw_color(to_color(palette.color(pixel)));
                 // Calculate coordinates
-                let window_x = (x as f32) * SCREEN_PIXEL_WIDTH_ON_WINDOW;
-                let window_y = (y as f32) * SCREEN_PIXEL_HEIGHT_ON_WINDOW;
+                let window_x = (x as f32) * pixel_width_on_window;
+                let window_y = (y as f32) * pixel_height_on_window;
                 // fill in pixel on screen
                 canvas
                     .fill_rect(Rect::new(
                         window_x as i32,
                         window_y as i32,
-                        SCREEN_PIXEL_WIDTH_ON_WINDOW as u32,
-                        SCREEN_PIXEL_HEIGHT_ON_WINDOW as u32,
+                        pixel_width_on_window as u32,
+                        pixel_height_on_window as u32,
                     ))
                     .unwrap();
             }
@@ -48,57 +443,451 @@ fn draw(canvas: &mut WindowCanvas, pixels: &[u8]) {
     canvas.present();
 }
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    if args.len() != 2 {
-        let name = if args.len() == 0 {
-            "chip8_emu"
-        } else {
-            &args[0][..]
-        };
-        print_usage(name);
-        return;
+// Shows why the emulator isn't running, if it isn't
+fn set_title(canvas: &mut WindowCanvas, halted: &Option<String>, paused: bool) {
+    let title = match halted {
+        Some(e) => format!("{} - halted: {}", TITLE, e),
+        None if paused => format!("{} - paused", TITLE),
+        None => TITLE.to_string(),
+    };
+    canvas.window_mut().set_title(&title).unwrap();
+}
+
+// Loads the ROM, then sets the CPU up with the command line settings over the ones the
+// config has for that ROM. A movie being played back overrides them all.
+fn create_cpu(
+    options: &Options,
+    config: &Config,
+) -> Result<(Cpu, Settings, Option<Movie>), String> {
+    let mut cpu = Cpu::new();
+    if let Some(seed) = options.seed {
+        cpu.seed_rng(seed);
     }
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
+    cpu.load_program(&options.rom)
+        .map_err(|e| format!("Could not load program {}: {}", options.rom, e))?;
+    let mut settings = config.configure(&mut cpu, options.settings.clone());
+    let movie = match &options.play {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            movie.start(&mut cpu)?;
+            let ipf = movie.instructions_per_frame;
+            settings.clock = Some(ipf.saturating_mul(frame::FRAME_RATE));
+            Some(movie)
+        }
+        None => None,
+    };
+    if let Some(path) = &options.trace {
+        let mut tracer = Tracer::create(path)
+            .map_err(|e| format!("Could not create trace file {}: {}", path, e))?;
+        tracer.addresses = options.trace_addresses.clone();
+        tracer.cycles = options.trace_cycles.clone();
+        cpu.set_tracer(Some(tracer));
+    }
+    Ok((cpu, settings, movie))
+}
 
-    let window = video_subsystem
-        .window("Chip8 Emulator", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
-        .position_centered()
-        .build()
-        .unwrap();
+fn run_headless(
+    cpu: &mut Cpu,
+    settings: &Settings,
+    frames: u64,
+    playing: Option<&Movie>,
+) -> Result<(), String> {
+    let mut runner = Runner::new(RunLength::Frames(frames));
+    runner.cycles_per_frame = settings.instructions_per_frame() as u64;
+    if let Some(movie) = playing {
+        runner.key_events = movie.key_events();
+    }
+    let mut desyncs = 0;
+    let result = runner.run_with(cpu, |frame, cpu| {
+        if let Some(Err(desync)) = playing.map(|movie| movie.check_frame(frame as usize, cpu)) {
+            eprintln!("{}", desync);
+            desyncs += 1;
+        }
+    });
+    print!("{}", headless::framebuffer_ascii(cpu.screen()));
+    print!("{}", headless::register_dump(cpu));
+    result.map_err(|e| format!("Emulation halted: {}", e))?;
+    if desyncs > 0 {
+        return Err(format!("The movie desynced {} times", desyncs));
+    }
+    Ok(())
+}
+
+fn run(
+    cpu: &mut Cpu,
+    options: &Options,
+    settings: &Settings,
+    playing: Option<Movie>,
+) -> Result<(), String> {
+    let instructions_per_frame = settings.instructions_per_frame();
+    let palette = settings.palette.unwrap_or_default();
+    let mut keymap = match &settings.keymap {
+        Some(keymap) => KeyMap::load(keymap)?,
+        None => KeyMap::default(),
+    };
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
 
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    let mut window = video_subsystem.window(
+        TITLE,
+        LORES_WIDTH as u32 * options.scale,
+        LORES_HEIGHT as u32 * options.scale,
+    );
+    window.position_centered();
+    if options.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    canvas.set_draw_color(to_color(palette.color(0)));
     canvas.clear();
     canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = sdl_context.event_pump()?;
 
-    let mut cpu = Cpu::new();
+    // Carry on silently if there's no audio device
+    let mut audio_device = if options.audio {
+        match sdl_context
+            .audio()
+            .and_then(|audio| open_audio(&audio, options.tone))
+        {
+            Ok(device) => Some(device),
+            Err(e) => {
+                eprintln!("Could not open audio, running without sound: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut muted = false;
+
+    // Set when the program crashes, leaving the last frame up until the window is closed
+    let mut halted: Option<String> = None;
+    let mut paused = options.paused;
+    set_title(&mut canvas, &halted, paused);
+
+    let mut rewind_buffer =
+        RewindBuffer::new(options.rewind_seconds).ok_or("The rewind buffer is too long")?;
+    let mut rewinding = false;
+
+    let mut limiter = FrameLimiter::default();
+    let mut fast_forward = false;
+    let mut slow_motion = false;
+
+    let mut debugger = if options.debug {
+        Some(Debugger::new())
+    } else {
+        None
+    };
 
-    cpu.load_program(args[1].as_str())
-        .expect("Could not load program");
+    // The keymap being built on the rebinding screen and how many keys it has so far
+    let mut rebinding: Option<(KeyMap, usize)> = None;
+
+    // Host keys only reach the keypad between frames, which is all a movie can record. Going
+    // back in time would make a recording or playback meaningless, so that's turned off.
+    let mut held_keys: u16 = 0;
+    let mut playing = playing;
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(cpu, instructions_per_frame));
+    let movie_active = playing.is_some() || recording.is_some();
+    let mut frame = 0;
+    let mut desyncs = 0;
+
+    let mut gif: Option<GifRecorder> = None;
 
     'running: loop {
-        cpu.emulate_cycle();
-        if cpu.draw_needed() {
-            draw(&mut canvas, cpu.get_pixel_data());
-        }
         for event in event_pump.poll_iter() {
+            if let Some((mut new_keymap, mut index)) = rebinding.take() {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => {}
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat: false,
+                        ..
+                    } => {
+                        // Each host key can only press one hex key, and hotkeys none
+                        let host_key = keycode.name();
+                        if !host_key.is_empty()
+                            && !keymap::is_hotkey(&host_key)
+                            && new_keymap.key_for(&host_key).is_none()
+                        {
+                            new_keymap.bind(&host_key, KEYPAD_LAYOUT[index]);
+                            index += 1;
+                        }
+                        if index == KEYPAD_LAYOUT.len() {
+                            println!("New keymap, save this to a file to use with --keymap:");
+                            print!("{}", new_keymap);
+                            keymap = new_keymap;
+                        } else {
+                            draw_rebinding(&mut canvas, &palette, index);
+                            rebinding = Some((new_keymap, index));
+                        }
+                    }
+                    _ => rebinding = Some((new_keymap, index)),
+                }
+                if rebinding.is_none() {
+                    set_title(&mut canvas, &halted, paused);
+                    draw(&mut canvas, cpu.screen(), &palette);
+                }
+                continue;
+            }
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
-                Event::KeyDown { .. } | Event::KeyUp { .. } => cpu.handle_input(event),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = !movie_active,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => fast_forward = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    ..
+                } => slow_motion = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backquote),
+                    ..
+                } => slow_motion = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    repeat: false,
+                    ..
+                } => muted = !muted,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => {
+                    paused = !paused;
+                    set_title(&mut canvas, &halted, paused);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => save_screenshot(cpu.screen(), &options.rom, options.scale, &palette),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => match gif.take() {
+                    Some(recorded) => save_gif(&recorded, &options.rom),
+                    None => {
+                        println!("Recording GIF, F6 stops");
+                        gif = Some(GifRecorder::new(options.scale, palette));
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => {
+                    // Let go of everything so no key stays held while the map changes
+                    held_keys = 0;
+                    rewinding = false;
+                    fast_forward = false;
+                    slow_motion = false;
+                    draw_rebinding(&mut canvas, &palette, 0);
+                    rebinding = Some((KeyMap::empty(), 0));
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keycode_to_slot(keycode).is_some() => {
+                    let slot = keycode_to_slot(keycode).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        save_state(cpu, &options.rom, slot);
+                    } else if movie_active {
+                        eprintln!("States can't be loaded while a movie is recording or playing");
+                    } else if load_state(cpu, &options.rom, slot) {
+                        // A good state replaces whatever crashed
+                        halted = None;
+                        set_title(&mut canvas, &halted, paused);
+                        draw(&mut canvas, cpu.screen(), &palette);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keymap.key_for(&keycode.name()) {
+                        held_keys |= 1 << key;
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keymap.key_for(&keycode.name()) {
+                        held_keys &= !(1 << key);
+                    }
+                }
                 _ => {}
             }
         }
 
-        const CLOCK_SPEED: u32 = 500; // HZ
-        const NANOS_PER_SECOND: u32 = 1_000_000_000;
-        const SLEEP_TIME: u32 = NANOS_PER_SECOND / CLOCK_SPEED;
-        ::std::thread::sleep(Duration::new(0, SLEEP_TIME));
+        if rewinding {
+            // Step back one snapshot per frame for as long as the key is held
+            if rewind_buffer.rewind(cpu) {
+                if halted.is_some() {
+                    halted = None;
+                    set_title(&mut canvas, &halted, paused);
+                }
+                draw(&mut canvas, cpu.screen(), &palette);
+            }
+        } else if halted.is_none() && !paused && rebinding.is_none() {
+            let movie_playing = match &playing {
+                Some(movie) => movie.play_frame(frame, cpu),
+                None => false,
+            };
+            if !movie_playing {
+                if playing.take().is_some() {
+                    println!("Movie finished after {} frames, {} desyncs", frame, desyncs);
+                }
+                movie::set_keys(cpu, held_keys);
+            }
+            let outcome = frame::run_frame(cpu, instructions_per_frame, |cpu| {
+                if let Some(debugger) = debugger.as_mut() {
+                    while debugger.should_pause(cpu) {
+                        set_sounding(&mut audio_device, false);
+                        draw(&mut canvas, cpu.screen(), &palette);
+                        if !debug_prompt(debugger, cpu) {
+                            return false;
+                        }
+                    }
+                }
+                true
+            });
+            match outcome {
+                Ok(FrameOutcome::Completed) => {
+                    rewind_buffer.push(cpu);
+                    if let Some(Err(desync)) =
+                        playing.as_ref().map(|movie| movie.check_frame(frame, cpu))
+                    {
+                        eprintln!("{}", desync);
+                        desyncs += 1;
+                    }
+                    if let Some(movie) = recording.as_mut() {
+                        movie.record_frame(movie::keys(cpu), cpu);
+                    }
+                    frame += 1;
+                }
+                // Frames only stop early when the debugger quits
+                Ok(FrameOutcome::Exited) | Ok(FrameOutcome::Stopped) => break 'running,
+                Err(e) => {
+                    eprintln!("Emulation halted: {}", e);
+                    if let Some(debugger) = debugger.as_mut() {
+                        // Drop into the debugger to poke around instead
+                        debugger.pause();
+                    } else {
+                        halted = Some(e.to_string());
+                        set_title(&mut canvas, &halted, paused);
+                    }
+                }
+            }
+        }
+        if cpu.draw_needed() && rebinding.is_none() {
+            draw(&mut canvas, cpu.screen(), &palette);
+            cpu.clear_draw_flag();
+        }
+
+        if let (Some(gif), None) = (gif.as_mut(), &rebinding) {
+            gif.capture(cpu.screen());
+        }
+
+        let running = halted.is_none() && !paused && !rewinding && rebinding.is_none();
+        set_sounding(
+            &mut audio_device,
+            running && !muted && cpu.is_sound_active(),
+        );
+
+        if fast_forward {
+            limiter.reset();
+        } else if slow_motion {
+            limiter.wait(SLOW_MOTION_FACTOR);
+        } else {
+            limiter.wait(1);
+        }
+    }
+    if let Some(gif) = &gif {
+        save_gif(gif, &options.rom);
+    }
+    if let (Some(movie), Some(path)) = (&recording, &options.record) {
+        movie.save(path)?;
+        println!(
+            "Recorded {} frames to {}",
+            movie.frames.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let name = if args.is_empty() {
+        "chip8_emu"
+    } else {
+        &args[0][..]
+    };
+    let args = args.get(1..).unwrap_or(&[]);
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_usage(name);
+        return;
+    }
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Try {} --help", name);
+            process::exit(2);
+        }
+    };
+
+    let setup = Config::from_options(options.no_config, options.config.as_deref())
+        .and_then(|config| create_cpu(&options, &config));
+    let (mut cpu, settings, playing) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let result = match options.headless {
+        Some(frames) => run_headless(&mut cpu, &settings, frames, playing.as_ref()),
+        None => run(&mut cpu, &options, &settings, playing),
+    };
+
+    if let Some(tracer) = cpu.take_tracer() {
+        if let Err(e) = tracer.finish() {
+            eprintln!("Could not write trace: {}", e);
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
@@ -1,65 +1,87 @@
+use crate::state::{self, StateReader, StateWriter};
+
 #[derive(Copy, Clone, Debug)]
-pub struct OutOfBoundsError;
+pub struct OutOfBoundsError {
+    pub address: u16,
+}
 
 pub type Result<T> = std::result::Result<T, OutOfBoundsError>;
 
-pub const MEMORY_SIZE: u16 = 4096;
+// XO-CHIP programs can address the full 64KB, older ones only use the first 4KB
+pub const MEMORY_SIZE: usize = 0x10000;
 
 pub struct Memory {
-    memory: [u8; MEMORY_SIZE as usize],
+    memory: Vec<u8>,
 }
 
 impl Default for Memory {
     fn default() -> Self {
         Memory {
-            memory: [0; MEMORY_SIZE as usize],
+            memory: vec![0; MEMORY_SIZE],
         }
     }
 }
 
 impl Memory {
     pub fn reset(&mut self) {
-        self.memory = [0; MEMORY_SIZE as usize];
+        for byte in self.memory.iter_mut() {
+            *byte = 0;
+        }
     }
 
-    pub fn write_u8(&mut self, address: u16, data: u8) -> Result<()> {
-        if address > MEMORY_SIZE {
-            return Err(OutOfBoundsError);
+    fn check_range(address: u16, size: usize) -> Result<()> {
+        if address as usize + size > MEMORY_SIZE {
+            return Err(OutOfBoundsError { address });
         }
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, address: u16, data: u8) -> Result<()> {
+        Self::check_range(address, 1)?;
         self.memory[address as usize] = data;
         Ok(())
     }
 
     pub fn write_data(&mut self, start_address: u16, data: &[u8]) -> Result<()> {
-        if start_address > MEMORY_SIZE {
-            return Err(OutOfBoundsError);
-        }
-        for (index, &byte) in data.iter().enumerate() {
-            self.write_u8(start_address + index as u16, byte)?;
-        }
+        Self::check_range(start_address, data.len())?;
+        let start = start_address as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
         Ok(())
     }
 
     pub fn get_u8(&self, address: u16) -> Result<u8> {
-        if address > MEMORY_SIZE {
-            return Err(OutOfBoundsError);
-        }
+        Self::check_range(address, 1)?;
         Ok(self.memory[address as usize])
     }
 
     pub fn get_u16(&self, address: u16) -> Result<u16> {
-        if address > MEMORY_SIZE || address + 1 > MEMORY_SIZE {
-            return Err(OutOfBoundsError);
-        }
+        Self::check_range(address, 2)?;
         let high_byte = self.memory[address as usize] as u16;
-        let low_byte = self.memory[(address + 1) as usize] as u16;
+        let low_byte = self.memory[address as usize + 1] as u16;
         Ok(high_byte << 8 | low_byte)
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> state::Result<()> {
+        reader.read_into(&mut self.memory)
+    }
+
+    /// Like `get_data`, but cut short at the end of memory instead of failing
+    pub fn peek(&self, address: u16, size: usize) -> &[u8] {
+        let start = address as usize;
+        let end = start.saturating_add(size).min(MEMORY_SIZE);
+        &self.memory[start..end]
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory[..]
+    }
+
     pub fn get_data(&self, address: u16, size: u16) -> Result<&[u8]> {
-        if address > MEMORY_SIZE - size {
-            return Err(OutOfBoundsError);
-        }
-        Ok(&self.memory[address as usize..(address + size) as usize])
+        Self::check_range(address, size as usize)?;
+        Ok(&self.memory[address as usize..address as usize + size as usize])
     }
 }
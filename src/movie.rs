@@ -0,0 +1,468 @@
+use crate::cpu::Cpu;
+use crate::headless::{self, KeyEvent};
+use crate::quirks::Quirks;
+use crate::sha1;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// The first line of every movie, followed by the format version
+const MAGIC: &str = "chip8-movie";
+const VERSION: u32 = 1;
+
+/// How many frames apart the framebuffer is checked during playback
+pub const CHECKPOINT_INTERVAL: usize = 60;
+
+/// The keypad during one frame, with a bit set for each hex key held down
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub keys: u16,
+    /// The framebuffer hash at the end of the frame
+    pub checkpoint: Option<u64>,
+}
+
+/// Playback reaching a checkpoint with a different screen than when it was recorded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Desync at frame {}: framebuffer hash {:016x}, expected {:016x}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for MovieError {}
+
+/// A recording of the keypad, frame by frame, along with everything else a run depends on so
+/// playing it back from power on gives the same run. Stored as text:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 0123456789abcdef0123456789abcdef01234567
+/// seed 42
+/// quirks shift_uses_vy logic_resets_vf
+/// ipf 8
+/// frames
+/// 0000
+/// 0010
+/// 0010 9c0e1bd5a77d3f08
+/// ```
+///
+/// Each frame line is the keys in hex, then every `CHECKPOINT_INTERVAL` frames the
+/// framebuffer hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_sha1: [u8; 20],
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub frames: Vec<Frame>,
+}
+
+// The quirks by the names movies store them under
+fn quirk_flags(quirks: &mut Quirks) -> [(&'static str, &mut bool); 5] {
+    [
+        ("shift_uses_vy", &mut quirks.shift_uses_vy),
+        (
+            "load_store_increments_i",
+            &mut quirks.load_store_increments_i,
+        ),
+        ("jump_uses_vx", &mut quirks.jump_uses_vx),
+        ("logic_resets_vf", &mut quirks.logic_resets_vf),
+        ("wrap_sprites", &mut quirks.wrap_sprites),
+    ]
+}
+
+/// The keypad as a movie frame stores it
+pub fn keys(cpu: &Cpu) -> u16 {
+    (0..16)
+        .filter(|&key| cpu.is_key_pressed(key))
+        .fold(0, |keys, key| keys | 1 << key)
+}
+
+/// Presses and releases keys to match `keys`. Releases go first so a key waited on with
+/// FX0A can't be one that was already down.
+pub fn set_keys(cpu: &mut Cpu, keys: u16) {
+    let current = self::keys(cpu);
+    for key in 0..16 {
+        if current & !keys & 1 << key != 0 {
+            cpu.release_key(key);
+        }
+    }
+    for key in 0..16 {
+        if keys & !current & 1 << key != 0 {
+            cpu.press_key(key);
+        }
+    }
+}
+
+impl Movie {
+    /// Starts recording a CPU that has just loaded its program and not run yet
+    pub fn new(cpu: &Cpu, instructions_per_frame: u32) -> Self {
+        Movie {
+            rom_sha1: cpu.rom_sha1().copied().unwrap_or_default(),
+            seed: cpu.rng_seed(),
+            quirks: cpu.quirks(),
+            instructions_per_frame,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds a frame that ran with `keys` held, checkpointing the screen it left
+    pub fn record_frame(&mut self, keys: u16, cpu: &Cpu) {
+        let checkpoint = if self.frames.len() % CHECKPOINT_INTERVAL == CHECKPOINT_INTERVAL - 1 {
+            Some(headless::framebuffer_hash(cpu.screen()))
+        } else {
+            None
+        };
+        self.frames.push(Frame { keys, checkpoint });
+    }
+
+    /// Sets a CPU that has just loaded its program up to play the movie back
+    pub fn start(&self, cpu: &mut Cpu) -> Result<(), String> {
+        if cpu.rom_sha1() != Some(&self.rom_sha1) {
+            return Err(format!(
+                "The movie was recorded with a different ROM, SHA-1 {}",
+                sha1::to_hex(&self.rom_sha1)
+            ));
+        }
+        cpu.seed_rng(self.seed);
+        cpu.set_quirks(self.quirks);
+        Ok(())
+    }
+
+    /// Sets the keypad for a frame before running it. Returns false once the movie is over.
+    pub fn play_frame(&self, frame: usize, cpu: &mut Cpu) -> bool {
+        match self.frames.get(frame) {
+            Some(recorded) => {
+                set_keys(cpu, recorded.keys);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compares the screen after a frame with the recording, if it has a checkpoint there
+    pub fn check_frame(&self, frame: usize, cpu: &Cpu) -> Result<(), Desync> {
+        let expected = match self
+            .frames
+            .get(frame)
+            .and_then(|recorded| recorded.checkpoint)
+        {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let actual = headless::framebuffer_hash(cpu.screen());
+        if actual != expected {
+            return Err(Desync {
+                frame,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// The key changes between frames, for feeding the movie to a headless `Runner`
+    pub fn key_events(&self) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        let mut current = 0;
+        for (step, frame) in self.frames.iter().enumerate() {
+            let step = step as u64;
+            let changed = current ^ frame.keys;
+            // In the same order as `set_keys`
+            for pressed in [false, true].iter().copied() {
+                for key in 0..16 {
+                    if changed & 1 << key != 0 && (frame.keys & 1 << key != 0) == pressed {
+                        events.push(KeyEvent { step, key, pressed });
+                    }
+                }
+            }
+            current = frame.keys;
+        }
+        events
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read movie {}: {}", path.display(), e))?;
+        Movie::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string())
+            .map_err(|e| format!("Could not write movie {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+        let error = |line: usize, message: String| MovieError { line, message };
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == format!("{} {}", MAGIC, VERSION) => {}
+            Some((_, line)) if line.starts_with(MAGIC) => {
+                return Err(error(1, format!("unsupported movie version '{}'", line)))
+            }
+            _ => return Err(error(1, "not a movie".to_string())),
+        }
+
+        let mut rom_sha1 = None;
+        let mut seed = None;
+        let mut quirks = None;
+        let mut instructions_per_frame = None;
+        let mut header_end = 1;
+        let mut has_frames = false;
+        for (number, line) in &mut lines {
+            header_end = number;
+            let line = line.trim();
+            if line == "frames" {
+                has_frames = true;
+                break;
+            }
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("").trim();
+            match key {
+                "rom" => {
+                    rom_sha1 = Some(
+                        parse_sha1(value)
+                            .ok_or_else(|| error(number, format!("'{}' is not a SHA-1", value)))?,
+                    )
+                }
+                "seed" => {
+                    let parsed = value
+                        .parse()
+                        .map_err(|_| error(number, format!("'{}' is not a seed", value)))?;
+                    seed = Some(parsed);
+                }
+                "quirks" => {
+                    let mut parsed = Quirks::default();
+                    for name in value.split_whitespace() {
+                        let mut flags = quirk_flags(&mut parsed);
+                        let (_, set) = flags
+                            .iter_mut()
+                            .find(|(flag, _)| *flag == name)
+                            .ok_or_else(|| error(number, format!("unknown quirk '{}'", name)))?;
+                        **set = true;
+                    }
+                    quirks = Some(parsed);
+                }
+                "ipf" => {
+                    let parsed = value.parse().ok().filter(|&ipf| ipf > 0).ok_or_else(|| {
+                        error(number, format!("'{}' is not an instruction count", value))
+                    })?;
+                    instructions_per_frame = Some(parsed);
+                }
+                _ => return Err(error(number, format!("unknown header '{}'", key))),
+            }
+        }
+        let missing = |name: &str| error(header_end, format!("the header has no {}", name));
+        if !has_frames {
+            return Err(missing("frames line"));
+        }
+        let mut movie = Movie {
+            rom_sha1: rom_sha1.ok_or_else(|| missing("rom"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+            instructions_per_frame: instructions_per_frame.ok_or_else(|| missing("ipf"))?,
+            frames: Vec::new(),
+        };
+
+        for (number, line) in lines {
+            let mut parts = line.split_whitespace();
+            let keys = match parts.next() {
+                Some(keys) => keys,
+                None => continue,
+            };
+            let keys = u16::from_str_radix(keys, 16)
+                .map_err(|_| error(number, format!("'{}' is not a keypad state", keys)))?;
+            let checkpoint = match parts.next() {
+                Some(hash) => Some(
+                    u64::from_str_radix(hash, 16)
+                        .map_err(|_| error(number, format!("'{}' is not a hash", hash)))?,
+                ),
+                None => None,
+            };
+            movie.frames.push(Frame { keys, checkpoint });
+        }
+        Ok(movie)
+    }
+}
+
+fn parse_sha1(value: &str) -> Option<[u8; 20]> {
+    if value.len() != 40 || !value.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (byte, hex) in digest.iter_mut().zip(value.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        writeln!(f, "rom {}", sha1::to_hex(&self.rom_sha1))?;
+        writeln!(f, "seed {}", self.seed)?;
+        let mut quirks = self.quirks;
+        write!(f, "quirks")?;
+        for (name, set) in quirk_flags(&mut quirks).iter() {
+            if **set {
+                write!(f, " {}", name)?;
+            }
+        }
+        writeln!(f)?;
+        writeln!(f, "ipf {}", self.instructions_per_frame)?;
+        writeln!(f, "frames")?;
+        for frame in &self.frames {
+            match frame.checkpoint {
+                Some(hash) => writeln!(f, "{:04x} {:016x}", frame.keys, hash)?,
+                None => writeln!(f, "{:04x}", frame.keys)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Preset;
+
+    fn movie(keys: &[u16]) -> Movie {
+        Movie {
+            rom_sha1: sha1::sha1(b"rom"),
+            seed: 42,
+            quirks: Preset::XoChip.quirks(),
+            instructions_per_frame: 1000,
+            frames: keys
+                .iter()
+                .map(|&keys| Frame {
+                    keys,
+                    checkpoint: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn movies_read_back_what_they_write() {
+        let mut original = movie(&[0, 0x8001, 0xFFFF, 0]);
+        original.frames[2].checkpoint = Some(0x0123_4567_89AB_CDEF);
+        assert_eq!(Movie::parse(&original.to_string()), Ok(original.clone()));
+
+        original.quirks = Quirks::default();
+        original.frames.clear();
+        assert_eq!(Movie::parse(&original.to_string()), Ok(original));
+    }
+
+    #[test]
+    fn the_header_must_be_complete() {
+        let text = movie(&[1, 2]).to_string();
+        let without = |name: &str| {
+            text.lines()
+                .filter(|line| !line.starts_with(name))
+                .map(|line| format!("{}\n", line))
+                .collect::<String>()
+        };
+        let error = |text: &str| Movie::parse(text).unwrap_err().message;
+        // Cut off before the frames, rather than an empty recording
+        let header = &text[..text.find("frames").unwrap()];
+        assert_eq!(error(header), "the header has no frames line");
+        assert_eq!(error(&without("seed")), "the header has no seed");
+        assert_eq!(error(&without("ipf")), "the header has no ipf");
+        assert_eq!(error(""), "not a movie");
+        assert_eq!(
+            Movie::parse(&text.replace("chip8-movie 1", "chip8-movie 2")),
+            Err(MovieError {
+                line: 1,
+                message: "unsupported movie version 'chip8-movie 2'".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn bad_frames_are_reported_at_their_line() {
+        let text = movie(&[]).to_string() + "0000\nzz\n";
+        assert_eq!(
+            Movie::parse(&text),
+            Err(MovieError {
+                line: 8,
+                message: "'zz' is not a keypad state".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn key_events_release_before_pressing() {
+        let events = movie(&[0, 0b011, 0b010, 0b100]).key_events();
+        let events = events
+            .iter()
+            .map(|event| (event.step, event.key, event.pressed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                (1, 0, true),
+                (1, 1, true),
+                (2, 0, false),
+                (3, 1, false),
+                (3, 2, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_changed_screen_at_a_checkpoint_is_a_desync() {
+        let mut cpu = Cpu::new();
+        let mut recorded = Movie::new(&cpu, 8);
+        for _ in 0..CHECKPOINT_INTERVAL {
+            recorded.record_frame(0, &cpu);
+        }
+        let checkpoint = CHECKPOINT_INTERVAL - 1;
+        assert!(recorded.frames[checkpoint].checkpoint.is_some());
+        assert_eq!(recorded.check_frame(checkpoint, &cpu), Ok(()));
+
+        cpu.memory_mut()
+            .write_data(0x200, &[0xF0, 0x29, 0xD0, 0x15])
+            .unwrap();
+        for _ in 0..2 {
+            cpu.emulate_cycle().unwrap();
+        }
+        // Only frames with a checkpoint are compared
+        assert_eq!(recorded.check_frame(0, &cpu), Ok(()));
+        let desync = recorded.check_frame(checkpoint, &cpu).unwrap_err();
+        assert_eq!(desync.frame, checkpoint);
+        assert_eq!(
+            desync.expected,
+            recorded.frames[checkpoint].checkpoint.unwrap()
+        );
+        assert_ne!(desync.actual, desync.expected);
+    }
+}
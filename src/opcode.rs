@@ -1,12 +1,25 @@
+use std::fmt;
+
 /// Opcodes
 /// Mnemonics are mine
-
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Opcode {
     CallAddress {
         address: u16,
     }, // 0NNN
+    ScrollDown {
+        lines: u8,
+    }, // 00CN
+    ScrollUp {
+        lines: u8,
+    }, // 00DN
     ClearScreen, // 00E0
     Return,      // 00EE
+    ScrollRight, // 00FB
+    ScrollLeft,  // 00FC
+    Exit,        // 00FD
+    LowRes,      // 00FE
+    HighRes,     // 00FF
     Goto {
         address: u16,
     }, // 1NNN
@@ -25,6 +38,14 @@ pub enum Opcode {
         register1: u8,
         register2: u8,
     }, // 5XY0
+    SaveRegisterRange {
+        register1: u8,
+        register2: u8,
+    }, // 5XY2
+    LoadRegisterRange {
+        register1: u8,
+        register2: u8,
+    }, // 5XY3
     SetRegister {
         register: u8,
         immediate: u8,
@@ -59,6 +80,7 @@ pub enum Opcode {
     }, // 8XY5
     RightShiftReg {
         register1: u8,
+        register2: u8,
     }, // 8XY6
     SubtractRegsOppositeOrder {
         register1: u8,
@@ -66,7 +88,8 @@ pub enum Opcode {
     }, // 8XY7
     LeftShiftReg {
         register1: u8,
-    }, // 8XY8
+        register2: u8,
+    }, // 8XYE
     IfRegsNotEqual {
         register1: u8,
         register2: u8,
@@ -85,13 +108,18 @@ pub enum Opcode {
         register1: u8,
         register2: u8,
         height: u8,
-    }, // DXYN
+    }, // DXYN, DXY0 draws a 16x16 sprite
     IfKeyEqual {
         register: u8,
     }, // EX9E
     IfKeyNotEqual {
         register: u8,
     }, // EXA1
+    SetILong,    // F000 NNNN, the address is the next instruction word
+    SelectPlanes {
+        planes: u8,
+    }, // FN01
+    LoadAudioPattern, // F002
     GetDelay {
         register: u8,
     }, // FX07
@@ -110,15 +138,27 @@ pub enum Opcode {
     GetSpriteAddr {
         register: u8,
     }, // FX29
+    GetBigSpriteAddr {
+        register: u8,
+    }, // FX30
     ToBinaryCodedDecimal {
         register: u8,
     }, // FX33
+    SetPitch {
+        register: u8,
+    }, // FX3A
     DumpRegistersUntil {
         register: u8,
     }, // FX55
     LoadRegistersUntil {
         register: u8,
     }, // FX65
+    SaveFlagsUntil {
+        register: u8,
+    }, // FX75
+    LoadFlagsUntil {
+        register: u8,
+    }, // FX85
     Unknown {
         opcode: u16,
     }, // Anything else
@@ -130,6 +170,21 @@ impl From<u16> for Opcode {
             0x0000 => match instruction & 0x0FFF {
                 0x0E0 => return Opcode::ClearScreen,
                 0x0EE => return Opcode::Return,
+                0x0FB => return Opcode::ScrollRight,
+                0x0FC => return Opcode::ScrollLeft,
+                0x0FD => return Opcode::Exit,
+                0x0FE => return Opcode::LowRes,
+                0x0FF => return Opcode::HighRes,
+                address if address & 0xFF0 == 0x0C0 => {
+                    return Opcode::ScrollDown {
+                        lines: (instruction & 0xF) as u8,
+                    }
+                }
+                address if address & 0xFF0 == 0x0D0 => {
+                    return Opcode::ScrollUp {
+                        lines: (instruction & 0xF) as u8,
+                    }
+                }
                 address => return Opcode::CallAddress { address },
             },
             0x1000 => {
@@ -154,14 +209,27 @@ impl From<u16> for Opcode {
                     immediate: (instruction & 0xFF) as u8,
                 }
             }
-            0x5000 => {
-                if instruction & 0xF == 0 {
+            0x5000 => match instruction & 0xF {
+                0x0 => {
                     return Opcode::IfRegsEqual {
                         register1: ((instruction & 0xF00) >> 8) as u8,
                         register2: ((instruction & 0xF0) >> 4) as u8,
                     };
                 }
-            }
+                0x2 => {
+                    return Opcode::SaveRegisterRange {
+                        register1: ((instruction & 0xF00) >> 8) as u8,
+                        register2: ((instruction & 0xF0) >> 4) as u8,
+                    };
+                }
+                0x3 => {
+                    return Opcode::LoadRegisterRange {
+                        register1: ((instruction & 0xF00) >> 8) as u8,
+                        register2: ((instruction & 0xF0) >> 4) as u8,
+                    };
+                }
+                _ => (),
+            },
             0x6000 => {
                 return Opcode::SetRegister {
                     register: ((instruction & 0xF00) >> 8) as u8,
@@ -214,6 +282,7 @@ impl From<u16> for Opcode {
                 0x6 => {
                     return Opcode::RightShiftReg {
                         register1: ((instruction & 0xF00) >> 8) as u8,
+                        register2: ((instruction & 0xF0) >> 4) as u8,
                     };
                 }
                 0x7 => {
@@ -225,6 +294,7 @@ impl From<u16> for Opcode {
                 0xE => {
                     return Opcode::LeftShiftReg {
                         register1: ((instruction & 0xF00) >> 8) as u8,
+                        register2: ((instruction & 0xF0) >> 4) as u8,
                     };
                 }
                 _ => (),
@@ -274,6 +344,13 @@ impl From<u16> for Opcode {
                 _ => (),
             },
             0xF000 => match instruction & 0xFF {
+                0x00 if instruction == 0xF000 => return Opcode::SetILong,
+                0x01 => {
+                    return Opcode::SelectPlanes {
+                        planes: ((instruction & 0xF00) >> 8) as u8,
+                    }
+                }
+                0x02 if instruction == 0xF002 => return Opcode::LoadAudioPattern,
                 0x07 => {
                     return Opcode::GetDelay {
                         register: ((instruction & 0xF00) >> 8) as u8,
@@ -304,11 +381,21 @@ impl From<u16> for Opcode {
                         register: ((instruction & 0xF00) >> 8) as u8,
                     }
                 }
+                0x30 => {
+                    return Opcode::GetBigSpriteAddr {
+                        register: ((instruction & 0xF00) >> 8) as u8,
+                    }
+                }
                 0x33 => {
                     return Opcode::ToBinaryCodedDecimal {
                         register: ((instruction & 0xF00) >> 8) as u8,
                     }
                 }
+                0x3A => {
+                    return Opcode::SetPitch {
+                        register: ((instruction & 0xF00) >> 8) as u8,
+                    }
+                }
                 0x55 => {
                     return Opcode::DumpRegistersUntil {
                         register: ((instruction & 0xF00) >> 8) as u8,
@@ -319,6 +406,16 @@ impl From<u16> for Opcode {
                         register: ((instruction & 0xF00) >> 8) as u8,
                     }
                 }
+                0x75 => {
+                    return Opcode::SaveFlagsUntil {
+                        register: ((instruction & 0xF00) >> 8) as u8,
+                    }
+                }
+                0x85 => {
+                    return Opcode::LoadFlagsUntil {
+                        register: ((instruction & 0xF00) >> 8) as u8,
+                    }
+                }
                 _ => (),
             },
             _ => unreachable!(),
@@ -329,3 +426,243 @@ impl From<u16> for Opcode {
         }
     }
 }
+
+/// The instruction word an opcode decodes from. Unknown opcodes encode to their raw word.
+impl From<Opcode> for u16 {
+    fn from(opcode: Opcode) -> u16 {
+        let xy = |x: u8, y: u8| (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |x: u8, nn: u8| (x as u16) << 8 | nn as u16;
+        let x = |x: u8| (x as u16) << 8;
+        match opcode {
+            Opcode::CallAddress { address } => address & 0xFFF,
+            Opcode::ScrollDown { lines } => 0x00C0 | lines as u16,
+            Opcode::ScrollUp { lines } => 0x00D0 | lines as u16,
+            Opcode::ClearScreen => 0x00E0,
+            Opcode::Return => 0x00EE,
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::Exit => 0x00FD,
+            Opcode::LowRes => 0x00FE,
+            Opcode::HighRes => 0x00FF,
+            Opcode::Goto { address } => 0x1000 | address & 0xFFF,
+            Opcode::CallSubroutine { address } => 0x2000 | address & 0xFFF,
+            Opcode::IfRegEqual {
+                register,
+                immediate,
+            } => 0x3000 | xnn(register, immediate),
+            Opcode::IfRegNotEqual {
+                register,
+                immediate,
+            } => 0x4000 | xnn(register, immediate),
+            Opcode::IfRegsEqual {
+                register1,
+                register2,
+            } => 0x5000 | xy(register1, register2),
+            Opcode::SaveRegisterRange {
+                register1,
+                register2,
+            } => 0x5002 | xy(register1, register2),
+            Opcode::LoadRegisterRange {
+                register1,
+                register2,
+            } => 0x5003 | xy(register1, register2),
+            Opcode::SetRegister {
+                register,
+                immediate,
+            } => 0x6000 | xnn(register, immediate),
+            Opcode::AddToRegister {
+                register,
+                immediate,
+            } => 0x7000 | xnn(register, immediate),
+            Opcode::MoveRegToReg {
+                register1,
+                register2,
+            } => 0x8000 | xy(register1, register2),
+            Opcode::BitwiseOrRegs {
+                register1,
+                register2,
+            } => 0x8001 | xy(register1, register2),
+            Opcode::BitwiseAndRegs {
+                register1,
+                register2,
+            } => 0x8002 | xy(register1, register2),
+            Opcode::BitwiseXorRegs {
+                register1,
+                register2,
+            } => 0x8003 | xy(register1, register2),
+            Opcode::AddRegs {
+                register1,
+                register2,
+            } => 0x8004 | xy(register1, register2),
+            Opcode::SubtractRegs {
+                register1,
+                register2,
+            } => 0x8005 | xy(register1, register2),
+            Opcode::RightShiftReg {
+                register1,
+                register2,
+            } => 0x8006 | xy(register1, register2),
+            Opcode::SubtractRegsOppositeOrder {
+                register1,
+                register2,
+            } => 0x8007 | xy(register1, register2),
+            Opcode::LeftShiftReg {
+                register1,
+                register2,
+            } => 0x800E | xy(register1, register2),
+            Opcode::IfRegsNotEqual {
+                register1,
+                register2,
+            } => 0x9000 | xy(register1, register2),
+            Opcode::SetIToAddress { address } => 0xA000 | address & 0xFFF,
+            Opcode::JumpIndirect { address } => 0xB000 | address & 0xFFF,
+            Opcode::Rand {
+                register,
+                immediate,
+            } => 0xC000 | xnn(register, immediate),
+            Opcode::Draw {
+                register1,
+                register2,
+                height,
+            } => 0xD000 | xy(register1, register2) | height as u16,
+            Opcode::IfKeyEqual { register } => 0xE09E | x(register),
+            Opcode::IfKeyNotEqual { register } => 0xE0A1 | x(register),
+            Opcode::SetILong => 0xF000,
+            Opcode::SelectPlanes { planes } => 0xF001 | x(planes),
+            Opcode::LoadAudioPattern => 0xF002,
+            Opcode::GetDelay { register } => 0xF007 | x(register),
+            Opcode::GetKey { register } => 0xF00A | x(register),
+            Opcode::SetDelay { register } => 0xF015 | x(register),
+            Opcode::SetSound { register } => 0xF018 | x(register),
+            Opcode::AddRegToI { register } => 0xF01E | x(register),
+            Opcode::GetSpriteAddr { register } => 0xF029 | x(register),
+            Opcode::GetBigSpriteAddr { register } => 0xF030 | x(register),
+            Opcode::ToBinaryCodedDecimal { register } => 0xF033 | x(register),
+            Opcode::SetPitch { register } => 0xF03A | x(register),
+            Opcode::DumpRegistersUntil { register } => 0xF055 | x(register),
+            Opcode::LoadRegistersUntil { register } => 0xF065 | x(register),
+            Opcode::SaveFlagsUntil { register } => 0xF075 | x(register),
+            Opcode::LoadFlagsUntil { register } => 0xF085 | x(register),
+            Opcode::Unknown { opcode } => opcode,
+        }
+    }
+}
+
+/// Standard assembler mnemonics, mostly from Cowgod's reference with the SUPER-CHIP and
+/// XO-CHIP additions
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Opcode::CallAddress { address } => write!(f, "SYS {:#05X}", address),
+            Opcode::ScrollDown { lines } => write!(f, "SCD {}", lines),
+            Opcode::ScrollUp { lines } => write!(f, "SCU {}", lines),
+            Opcode::ClearScreen => write!(f, "CLS"),
+            Opcode::Return => write!(f, "RET"),
+            Opcode::ScrollRight => write!(f, "SCR"),
+            Opcode::ScrollLeft => write!(f, "SCL"),
+            Opcode::Exit => write!(f, "EXIT"),
+            Opcode::LowRes => write!(f, "LOW"),
+            Opcode::HighRes => write!(f, "HIGH"),
+            Opcode::Goto { address } => write!(f, "JP {:#05X}", address),
+            Opcode::CallSubroutine { address } => write!(f, "CALL {:#05X}", address),
+            Opcode::IfRegEqual {
+                register,
+                immediate,
+            } => write!(f, "SE V{:X}, {:#04X}", register, immediate),
+            Opcode::IfRegNotEqual {
+                register,
+                immediate,
+            } => write!(f, "SNE V{:X}, {:#04X}", register, immediate),
+            Opcode::IfRegsEqual {
+                register1,
+                register2,
+            } => write!(f, "SE V{:X}, V{:X}", register1, register2),
+            Opcode::SaveRegisterRange {
+                register1,
+                register2,
+            } => write!(f, "SAVE V{:X} - V{:X}", register1, register2),
+            Opcode::LoadRegisterRange {
+                register1,
+                register2,
+            } => write!(f, "LOAD V{:X} - V{:X}", register1, register2),
+            Opcode::SetRegister {
+                register,
+                immediate,
+            } => write!(f, "LD V{:X}, {:#04X}", register, immediate),
+            Opcode::AddToRegister {
+                register,
+                immediate,
+            } => write!(f, "ADD V{:X}, {:#04X}", register, immediate),
+            Opcode::MoveRegToReg {
+                register1,
+                register2,
+            } => write!(f, "LD V{:X}, V{:X}", register1, register2),
+            Opcode::BitwiseOrRegs {
+                register1,
+                register2,
+            } => write!(f, "OR V{:X}, V{:X}", register1, register2),
+            Opcode::BitwiseAndRegs {
+                register1,
+                register2,
+            } => write!(f, "AND V{:X}, V{:X}", register1, register2),
+            Opcode::BitwiseXorRegs {
+                register1,
+                register2,
+            } => write!(f, "XOR V{:X}, V{:X}", register1, register2),
+            Opcode::AddRegs {
+                register1,
+                register2,
+            } => write!(f, "ADD V{:X}, V{:X}", register1, register2),
+            Opcode::SubtractRegs {
+                register1,
+                register2,
+            } => write!(f, "SUB V{:X}, V{:X}", register1, register2),
+            Opcode::RightShiftReg {
+                register1,
+                register2,
+            } => write!(f, "SHR V{:X}, V{:X}", register1, register2),
+            Opcode::SubtractRegsOppositeOrder {
+                register1,
+                register2,
+            } => write!(f, "SUBN V{:X}, V{:X}", register1, register2),
+            Opcode::LeftShiftReg {
+                register1,
+                register2,
+            } => write!(f, "SHL V{:X}, V{:X}", register1, register2),
+            Opcode::IfRegsNotEqual {
+                register1,
+                register2,
+            } => write!(f, "SNE V{:X}, V{:X}", register1, register2),
+            Opcode::SetIToAddress { address } => write!(f, "LD I, {:#05X}", address),
+            Opcode::JumpIndirect { address } => write!(f, "JP V0, {:#05X}", address),
+            Opcode::Rand {
+                register,
+                immediate,
+            } => write!(f, "RND V{:X}, {:#04X}", register, immediate),
+            Opcode::Draw {
+                register1,
+                register2,
+                height,
+            } => write!(f, "DRW V{:X}, V{:X}, {}", register1, register2, height),
+            Opcode::IfKeyEqual { register } => write!(f, "SKP V{:X}", register),
+            Opcode::IfKeyNotEqual { register } => write!(f, "SKNP V{:X}", register),
+            Opcode::SetILong => write!(f, "LD I, LONG"),
+            Opcode::SelectPlanes { planes } => write!(f, "PLANE {}", planes),
+            Opcode::LoadAudioPattern => write!(f, "AUDIO"),
+            Opcode::GetDelay { register } => write!(f, "LD V{:X}, DT", register),
+            Opcode::GetKey { register } => write!(f, "LD V{:X}, K", register),
+            Opcode::SetDelay { register } => write!(f, "LD DT, V{:X}", register),
+            Opcode::SetSound { register } => write!(f, "LD ST, V{:X}", register),
+            Opcode::AddRegToI { register } => write!(f, "ADD I, V{:X}", register),
+            Opcode::GetSpriteAddr { register } => write!(f, "LD F, V{:X}", register),
+            Opcode::GetBigSpriteAddr { register } => write!(f, "LD HF, V{:X}", register),
+            Opcode::ToBinaryCodedDecimal { register } => write!(f, "LD B, V{:X}", register),
+            Opcode::SetPitch { register } => write!(f, "PITCH V{:X}", register),
+            Opcode::DumpRegistersUntil { register } => write!(f, "LD [I], V{:X}", register),
+            Opcode::LoadRegistersUntil { register } => write!(f, "LD V{:X}, [I]", register),
+            Opcode::SaveFlagsUntil { register } => write!(f, "LD R, V{:X}", register),
+            Opcode::LoadFlagsUntil { register } => write!(f, "LD V{:X}, R", register),
+            Opcode::Unknown { opcode } => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+}
+
+/// Colours for each combination of the two XO-CHIP planes: neither, first, second, both
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::PRESETS[0].1
+    }
+}
+
+impl Palette {
+    pub const PRESETS: [(&'static str, Palette); 4] = [
+        (
+            "mono",
+            Palette {
+                colors: [
+                    Rgb::new(0, 0, 0),
+                    Rgb::new(255, 255, 255),
+                    Rgb::new(170, 170, 170),
+                    Rgb::new(85, 85, 85),
+                ],
+            },
+        ),
+        (
+            "amber",
+            Palette {
+                colors: [
+                    Rgb::new(26, 16, 0),
+                    Rgb::new(255, 176, 0),
+                    Rgb::new(178, 112, 0),
+                    Rgb::new(102, 64, 0),
+                ],
+            },
+        ),
+        (
+            "green",
+            Palette {
+                colors: [
+                    Rgb::new(0, 20, 0),
+                    Rgb::new(51, 255, 51),
+                    Rgb::new(34, 170, 34),
+                    Rgb::new(17, 85, 17),
+                ],
+            },
+        ),
+        (
+            "lcd",
+            Palette {
+                colors: [
+                    Rgb::new(155, 188, 15),
+                    Rgb::new(15, 56, 15),
+                    Rgb::new(48, 98, 48),
+                    Rgb::new(139, 172, 15),
+                ],
+            },
+        ),
+    ];
+
+    /// The colour to show a pixel value from `Screen::get_pixel_data` in
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[pixel as usize & 0b11]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteError(pub String);
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is not a palette name or a list of 2 or 4 RRGGBB colours",
+            self.0
+        )
+    }
+}
+
+impl Error for PaletteError {}
+
+fn parse_color(value: &str) -> Option<Rgb> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Rgb::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// A preset name, or comma separated `RRGGBB` colours. With only a background and a
+/// foreground colour, the other XO-CHIP planes are drawn in the foreground colour too.
+impl FromStr for Palette {
+    type Err = PaletteError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some((_, palette)) = Palette::PRESETS.iter().find(|(name, _)| *name == value) {
+            return Ok(*palette);
+        }
+        let colors = value
+            .split(',')
+            .map(parse_color)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| PaletteError(value.to_string()))?;
+        match colors[..] {
+            [background, foreground] => Ok(Palette {
+                colors: [background, foreground, foreground, foreground],
+            }),
+            [a, b, c, d] => Ok(Palette {
+                colors: [a, b, c, d],
+            }),
+            _ => Err(PaletteError(value.to_string())),
+        }
+    }
+}
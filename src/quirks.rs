@@ -0,0 +1,113 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Toggles for the opcodes whose behaviour differs between CHIP-8 implementations.
+/// The default matches what this emulator has always done.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register stored/loaded
+    pub load_store_increments_i: bool,
+    /// BXNN jumps to VX + XNN instead of BNNN jumping to V0 + NNN
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// Sprites wrap around the edges of the screen instead of being clipped
+    pub wrap_sprites: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Preset {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [
+        Preset::CosmacVip,
+        Preset::Chip48,
+        Preset::SuperChip,
+        Preset::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::CosmacVip => "vip",
+            Preset::Chip48 => "chip48",
+            Preset::SuperChip => "schip",
+            Preset::XoChip => "xochip",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Preset::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                logic_resets_vf: true,
+                wrap_sprites: false,
+            },
+            Preset::Chip48 => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: true,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                wrap_sprites: false,
+            },
+            Preset::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                logic_resets_vf: false,
+                wrap_sprites: false,
+            },
+            Preset::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                logic_resets_vf: false,
+                wrap_sprites: true,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UnknownPresetError(pub String);
+
+impl fmt::Display for UnknownPresetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown quirks preset '{}', expected one of: ", self.0)?;
+        let names = Preset::ALL
+            .iter()
+            .map(|preset| preset.name())
+            .collect::<Vec<_>>();
+        f.write_str(&names.join(", "))
+    }
+}
+
+impl std::error::Error for UnknownPresetError {}
+
+impl FromStr for Preset {
+    type Err = UnknownPresetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8" | "chip-8" => Ok(Preset::CosmacVip),
+            "chip48" | "chip-48" => Ok(Preset::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(Preset::SuperChip),
+            "xochip" | "xo-chip" => Ok(Preset::XoChip),
+            _ => Err(UnknownPresetError(s.to_string())),
+        }
+    }
+}
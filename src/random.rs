@@ -0,0 +1,63 @@
+use crate::state::{self, StateError, StateReader, StateWriter};
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// The random number generator behind RND. It's a xorshift64*, whose whole state is one
+/// number, so it can go in save states and the same seed always gives the same numbers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed out with SplitMix64, so small seeds don't start with mostly zero
+        // bits and no seed leaves the state at zero, which xorshift can never leave
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Random {
+            seed,
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// Seeded differently every run, from the keys std picks for hash maps
+    pub fn from_entropy() -> Self {
+        Random::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// The seed this started from, to reproduce a run with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// The top bits, which are the best mixed
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.seed);
+        writer.write_u64(self.state);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> state::Result<()> {
+        self.seed = reader.read_u64()?;
+        self.state = reader.read_u64()?;
+        if self.state == 0 {
+            return Err(StateError::Invalid("random number generator"));
+        }
+        Ok(())
+    }
+}
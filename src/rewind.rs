@@ -0,0 +1,212 @@
+use crate::cpu::Cpu;
+use crate::frame;
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+pub const FRAMES_PER_SECOND: usize = frame::FRAME_RATE as usize;
+
+// Changed bytes closer together than this are stored as one run
+const MAX_GAP: usize = 8;
+
+/// A ring buffer of per-frame snapshots for stepping backwards through gameplay. Only the
+/// newest snapshot is kept whole; each older one is stored as the difference from the
+/// snapshot after it, which is tiny as most of memory and the screen stay the same between
+/// frames.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // deltas.back() turns the latest snapshot into the one before it
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// A buffer holding `seconds` of frames, or None if that's more frames than fit in a usize
+    pub fn new(seconds: usize) -> Option<Self> {
+        let frames = seconds.checked_mul(FRAMES_PER_SECOND)?;
+        Some(Self::with_capacity(frames))
+    }
+
+    /// A buffer holding up to `frames` snapshots
+    pub fn with_capacity(frames: usize) -> Self {
+        RewindBuffer {
+            capacity: frames,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of snapshots held
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the state of the CPU at the end of a frame
+    pub fn push(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+        let state = cpu.save_state();
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+            while self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Steps the CPU back to the newest snapshot and drops it, so repeated calls go further
+    /// back in time. Returns false once the buffer is empty.
+    pub fn rewind(&mut self, cpu: &mut Cpu) -> bool {
+        let latest = match self.latest.take() {
+            Some(latest) => latest,
+            None => return false,
+        };
+        cpu.load_state(&latest)
+            .expect("rewind snapshots come from save_state");
+        self.latest = self
+            .deltas
+            .pop_back()
+            .map(|delta| apply_delta(&latest, &delta));
+        true
+    }
+}
+
+// A delta holds the length of the target followed by runs of (offset, length, bytes) where
+// the bytes are XORed with the source. Past the end of the shorter one everything is XORed
+// with zero.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let byte_at = |data: &[u8], index: usize| data.get(index).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(to.len() as u32).to_le_bytes());
+    let mut index = 0;
+    while index < len {
+        if byte_at(from, index) == byte_at(to, index) {
+            index += 1;
+            continue;
+        }
+        // Extend the run until there is a long enough stretch of unchanged bytes
+        let start = index;
+        let mut end = index + 1;
+        let mut scan = end;
+        while scan < len && scan - end < MAX_GAP {
+            if byte_at(from, scan) != byte_at(to, scan) {
+                end = scan + 1;
+            }
+            scan += 1;
+        }
+        delta.extend_from_slice(&(start as u32).to_le_bytes());
+        delta.extend_from_slice(&((end - start) as u32).to_le_bytes());
+        delta.extend((start..end).map(|i| byte_at(from, i) ^ byte_at(to, i)));
+        index = end;
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(delta[offset..offset + 4].try_into().unwrap()) as usize;
+    let len = read_u32(0);
+    let mut to = from.to_vec();
+    to.resize(len.max(from.len()), 0);
+    let mut offset = 4;
+    while offset < delta.len() {
+        let start = read_u32(offset);
+        let run = read_u32(offset + 4);
+        offset += 8;
+        for (byte, &change) in to[start..start + run]
+            .iter_mut()
+            .zip(&delta[offset..offset + run])
+        {
+            *byte ^= change;
+        }
+        offset += run;
+    }
+    to.truncate(len);
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CPU that differs a little from one frame to the next, the way a running one does
+    fn cpu_at(frame: u8) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.seed_rng(1);
+        cpu.set_register(frame % 16, frame);
+        cpu.set_i_reg(0x300 + frame as u16);
+        cpu.set_delay_timer(255 - frame);
+        let data = (0..frame).collect::<Vec<_>>();
+        cpu.memory_mut().write_data(0x400, &data).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn rewinding_restores_every_snapshot_newest_first() {
+        let mut buffer = RewindBuffer::with_capacity(100);
+        let states = (0..20)
+            .map(|frame| cpu_at(frame).save_state())
+            .collect::<Vec<_>>();
+        for frame in 0..20 {
+            buffer.push(&cpu_at(frame));
+        }
+        assert_eq!(buffer.len(), 20);
+
+        let mut cpu = Cpu::new();
+        for expected in states.iter().rev() {
+            assert!(buffer.rewind(&mut cpu));
+            assert_eq!(&cpu.save_state(), expected);
+        }
+        assert!(!buffer.rewind(&mut cpu));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn the_oldest_snapshots_are_dropped_when_full() {
+        let mut buffer = RewindBuffer::with_capacity(5);
+        let states = (0..12)
+            .map(|frame| cpu_at(frame).save_state())
+            .collect::<Vec<_>>();
+        for frame in 0..12 {
+            buffer.push(&cpu_at(frame));
+        }
+        assert_eq!(buffer.len(), 5);
+
+        let mut cpu = Cpu::new();
+        for expected in states[7..].iter().rev() {
+            assert!(buffer.rewind(&mut cpu));
+            assert_eq!(&cpu.save_state(), expected);
+        }
+        assert!(!buffer.rewind(&mut cpu));
+    }
+
+    #[test]
+    fn deltas_round_trip_between_different_lengths() {
+        let short = vec![1, 2, 3, 4, 5];
+        let mut long = vec![0; 40];
+        long[3] = 9;
+        long[30] = 7;
+        for (from, to) in [(&short, &long), (&long, &short), (&long, &long)].iter() {
+            assert_eq!(&apply_delta(from, &encode_delta(from, to)), *to);
+        }
+    }
+}
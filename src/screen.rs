@@ -1,51 +1,157 @@
-pub const WIDTH: u16 = 64;
-pub const HEIGHT: u16 = 32;
-pub const SIZE: u16 = WIDTH * HEIGHT;
+use crate::state::{self, StateError, StateReader, StateWriter};
+
+pub const LORES_WIDTH: u16 = 64;
+pub const LORES_HEIGHT: u16 = 32;
+pub const HIRES_WIDTH: u16 = 128;
+pub const HIRES_HEIGHT: u16 = 64;
+
+// XO-CHIP has two bit planes, so each pixel holds one of four colours
+pub const PLANE_COUNT: u8 = 2;
+pub const ALL_PLANES: u8 = 0b11;
 
 pub struct Screen {
-    screen: [u8; SIZE as usize],
+    width: u16,
+    height: u16,
+    screen: Vec<u8>,
+    // Bit mask of the planes drawing, clearing and scrolling apply to
+    planes: u8,
 }
 
 impl Default for Screen {
     fn default() -> Self {
         Screen {
-            screen: [0; SIZE as usize],
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
+            screen: vec![0; (LORES_WIDTH * LORES_HEIGHT) as usize],
+            planes: 1,
         }
     }
 }
 
 impl Screen {
     pub fn reset(&mut self) {
-        self.screen = [0; SIZE as usize];
+        *self = Self::default();
+    }
+
+    pub fn clear(&mut self) {
+        let planes = self.planes;
+        for pixel in self.screen.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    pub fn selected_plane_count(&self) -> u16 {
+        self.planes.count_ones() as u16
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
     }
 
-    fn index(x: u16, y: u16) -> usize {
-        let index = x + (y * WIDTH);
-        assert!(index < SIZE);
-        index as usize
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    /// Switches between the 64x32 and 128x64 modes, which clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (LORES_WIDTH, LORES_HEIGHT)
+        };
+        self.width = width;
+        self.height = height;
+        self.screen = vec![0; (width * height) as usize];
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        assert!(x < self.width && y < self.height);
+        (x + (y * self.width)) as usize
     }
 
     pub fn get_pixel(&self, x: u16, y: u16) -> u8 {
-        self.screen[Self::index(x, y)]
+        self.screen[self.index(x, y)]
+    }
+
+    /// Toggles a pixel on the given plane, returning whether it was turned off
+    pub fn toggle_pixel(&mut self, x: u16, y: u16, plane: u8) -> bool {
+        let index = self.index(x, y);
+        let ret = self.screen[index] & plane != 0;
+        self.screen[index] ^= plane;
+        ret
+    }
+
+    /// Draws an 8 pixel wide sprite, returning whether any pixel was turned off. Pixels falling
+    /// off the edge of the screen are clipped, or wrapped around to the other side if `wrap` is
+    /// set. With more than one plane selected, `sprite` holds the data for each plane in turn.
+    pub fn draw_sprite(&mut self, x: u16, y: u16, sprite: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, sprite, 1, wrap)
+    }
+
+    /// Draws a 16x16 SUPER-CHIP sprite, stored as two bytes per line
+    pub fn draw_large_sprite(&mut self, x: u16, y: u16, sprite: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, sprite, 2, wrap)
     }
 
-    pub fn toggle_pixel(&mut self, x: u16, y: u16) -> bool {
-        let ret = self.get_pixel(x, y) == 1;
-        self.screen[Self::index(x, y)] ^= 1;
+    fn draw(&mut self, x: u16, y: u16, sprite: &[u8], bytes_per_line: usize, wrap: bool) -> bool {
+        let plane_count = self.selected_plane_count() as usize;
+        if plane_count == 0 || sprite.is_empty() {
+            return false;
+        }
+        let bytes_per_plane = sprite.len() / plane_count;
+        let selected = self.planes;
+        let planes = (0..PLANE_COUNT)
+            .map(|plane| 1 << plane)
+            .filter(|plane| selected & plane != 0);
+        let mut ret = false;
+        for (plane, plane_sprite) in planes.zip(sprite.chunks(bytes_per_plane)) {
+            if self.draw_plane(x, y, plane_sprite, bytes_per_line, wrap, plane) {
+                ret = true;
+            }
+        }
         ret
     }
 
-    pub fn draw_sprite(&mut self, x: u16, y: u16, sprite: &[u8]) -> bool {
+    fn draw_plane(
+        &mut self,
+        x: u16,
+        y: u16,
+        sprite: &[u8],
+        bytes_per_line: usize,
+        wrap: bool,
+        plane: u8,
+    ) -> bool {
         let mut ret = false;
-        let x = x % WIDTH;
-        let y = y % HEIGHT;
-        for (line, &pixel) in sprite.iter().enumerate() {
-            for bit in 0..8u8 {
-                if pixel & (0x80 >> bit) != 0 {
-                    if x + (bit as u16) < WIDTH && y + (line as u16) < HEIGHT {
-                        if self.toggle_pixel(x + bit as u16, y + line as u16) {
-                            ret = true;
-                        }
+        let x = x % self.width;
+        let y = y % self.height;
+        for (line, bytes) in sprite.chunks(bytes_per_line).enumerate() {
+            for (byte_index, &pixel) in bytes.iter().enumerate() {
+                for bit in 0..8u8 {
+                    if pixel & (0x80 >> bit) == 0 {
+                        continue;
+                    }
+                    let mut pixel_x = x + (byte_index * 8) as u16 + bit as u16;
+                    let mut pixel_y = y + line as u16;
+                    if wrap {
+                        pixel_x %= self.width;
+                        pixel_y %= self.height;
+                    } else if pixel_x >= self.width || pixel_y >= self.height {
+                        continue;
+                    }
+                    if self.toggle_pixel(pixel_x, pixel_y, plane) {
+                        ret = true;
                     }
                 }
             }
@@ -54,6 +160,61 @@ impl Screen {
         ret
     }
 
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll(0, lines as i32);
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll(0, -(lines as i32));
+    }
+
+    pub fn scroll_right(&mut self, columns: u16) {
+        self.scroll(columns as i32, 0);
+    }
+
+    pub fn scroll_left(&mut self, columns: u16) {
+        self.scroll(-(columns as i32), 0);
+    }
+
+    // Moves the selected planes by the given offset, leaving the other plane where it is
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let planes = self.planes;
+        let width = self.width as i32;
+        let height = self.height as i32;
+        let old = self.screen.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if from_x >= 0 && from_x < width && from_y >= 0 && from_y < height {
+                    old[(from_x + from_y * width) as usize] & planes
+                } else {
+                    0
+                };
+                let index = (x + y * width) as usize;
+                self.screen[index] = (old[index] & !planes) | moved;
+            }
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.is_hires());
+        writer.write_u8(self.planes);
+        writer.write_bytes(&self.screen);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> state::Result<()> {
+        self.set_hires(reader.read_bool()?);
+        self.planes = reader.read_u8()?;
+        if self.planes & !ALL_PLANES != 0 {
+            return Err(StateError::Invalid("plane selection"));
+        }
+        reader.read_into(&mut self.screen)?;
+        if self.screen.iter().any(|&pixel| pixel & !ALL_PLANES != 0) {
+            return Err(StateError::Invalid("pixel"));
+        }
+        Ok(())
+    }
+
     pub fn get_pixel_data(&self) -> &[u8] {
         &self.screen[..]
     }
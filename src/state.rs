@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::fmt;
+
+// Every save state starts with this, followed by the format version
+pub const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u8 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingData,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::TrailingData => write!(f, "Save state has trailing data"),
+            StateError::Invalid(what) => write!(f, "Save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub type Result<T> = std::result::Result<T, StateError>;
+
+/// Appends big endian fields to a save state
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut writer = StateWriter::default();
+        writer.write_bytes(MAGIC);
+        writer.write_u8(VERSION);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads back the fields written by a `StateWriter`, in the same order
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let mut reader = StateReader { data };
+        let magic = reader
+            .read_bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?;
+        if magic != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn read_bytes(&mut self, size: usize) -> Result<&'a [u8]> {
+        if self.data.len() < size {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<()> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let mut bytes = [0; 2];
+        self.read_into(&mut bytes)?;
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        self.read_into(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Checks that the whole state has been read
+    pub fn finish(self) -> Result<()> {
+        if !self.data.is_empty() {
+            return Err(StateError::TrailingData);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        StateWriter::new().finish()
+    }
+
+    #[test]
+    fn fields_read_back_in_order() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(7);
+        writer.write_bool(true);
+        writer.write_u16(0x1234);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_bytes(b"abc");
+        let data = writer.finish();
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.read_u8(), Ok(7));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_u16(), Ok(0x1234));
+        assert_eq!(reader.read_u64(), Ok(u64::MAX - 1));
+        assert_eq!(reader.read_bytes(3), Ok(&b"abc"[..]));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn the_header_is_checked() {
+        assert_eq!(StateReader::new(b"").err(), Some(StateError::BadMagic));
+        assert_eq!(StateReader::new(b"C8S").err(), Some(StateError::BadMagic));
+        assert_eq!(
+            StateReader::new(b"PNG\x00\x03").err(),
+            Some(StateError::BadMagic)
+        );
+        assert_eq!(StateReader::new(b"C8ST").err(), Some(StateError::Truncated));
+        assert_eq!(
+            StateReader::new(b"C8ST\x02").err(),
+            Some(StateError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn short_and_long_states_are_rejected() {
+        let mut data = header();
+        data.push(1);
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.read_u16(), Err(StateError::Truncated));
+
+        let reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.finish(), Err(StateError::TrailingData));
+    }
+
+    #[test]
+    fn flags_must_be_zero_or_one() {
+        let mut data = header();
+        data.extend_from_slice(&[0, 1, 2]);
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(reader.read_bool(), Ok(false));
+        assert_eq!(reader.read_bool(), Ok(true));
+        assert_eq!(reader.read_bool(), Err(StateError::Invalid("flag")));
+    }
+}
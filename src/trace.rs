@@ -0,0 +1,263 @@
+use crate::cpu::Cpu;
+use crate::opcode::Opcode;
+use crate::util::parse_number;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Writes a line for every instruction the CPU executes, for diffing against other
+/// emulators. Each line shows the state just before the instruction runs.
+pub struct Tracer {
+    output: Box<dyn Write + Send>,
+    /// Only trace instructions at these addresses
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// Only trace these cycles, counting from 0 at the first instruction traced or not
+    pub cycles: Option<RangeInclusive<u64>>,
+    cycle: u64,
+    // The first write that failed, after which tracing stops
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write + Send>) -> Self {
+        Tracer {
+            output,
+            addresses: None,
+            cycles: None,
+            cycle: 0,
+            error: None,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(Box::new(BufWriter::new(file))))
+    }
+
+    /// Called by the CPU before it executes an instruction
+    pub fn trace(&mut self, cpu: &Cpu) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.error.is_some() {
+            return;
+        }
+        let pc = cpu.program_counter();
+        let in_addresses = match &self.addresses {
+            Some(range) => range.contains(&pc),
+            None => true,
+        };
+        let in_cycles = match &self.cycles {
+            Some(range) => range.contains(&cycle),
+            None => true,
+        };
+        if in_addresses && in_cycles {
+            if let Err(e) = writeln!(self.output, "{}", trace_line(cpu, cycle)) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Flushes the output, returning the first error hit while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.output.flush()
+    }
+}
+
+// Unreadable memory shows up as 0000 rather than failing the trace
+fn trace_line(cpu: &Cpu, cycle: u64) -> String {
+    let pc = cpu.program_counter();
+    let word = cpu.memory().get_u16(pc).unwrap_or(0);
+    let opcode = Opcode::from(word);
+    let disassembly = match opcode {
+        Opcode::SetILong => {
+            let address = cpu.memory().get_u16(pc.wrapping_add(2)).unwrap_or(0);
+            format!("LD I, LONG {:#06X}", address)
+        }
+        _ => opcode.to_string(),
+    };
+    let registers = cpu
+        .registers()
+        .iter()
+        .map(|value| format!("{:02X}", value))
+        .collect::<Vec<_>>();
+    format!(
+        "{:08} {:04X} {:04X} {:<22} V={} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+        cycle,
+        pc,
+        word,
+        disassembly,
+        registers.join(" "),
+        cpu.i_reg(),
+        cpu.stack().len(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    )
+}
+
+/// Parses `start-end`, where either end may be left out to leave that side open
+pub fn parse_range(value: &str) -> Result<RangeInclusive<u64>, String> {
+    let mut parts = value.splitn(2, '-');
+    let start = parts.next().unwrap();
+    let end = parts
+        .next()
+        .ok_or_else(|| format!("'{}' should be <start>-<end>", value))?;
+    let start = if start.is_empty() {
+        0
+    } else {
+        parse_number(start)?
+    };
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        parse_number(end)?
+    };
+    if start > end {
+        return Err(format!("'{}' ends before it starts", value));
+    }
+    Ok(start..=end)
+}
+
+/// Like `parse_range` but for addresses, so an open end stops at the end of memory
+pub fn parse_address_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let range = parse_range(value)?;
+    let end = (*range.end()).min(0xFFFF);
+    if *range.start() > end {
+        return Err(format!("'{}' is not an address range", value));
+    }
+    Ok(*range.start() as u16..=end as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Output that can still be read once the tracer owns it
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct BrokenOutput;
+
+    impl Write for BrokenOutput {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The cycle and address of each traced line, running a four instruction loop
+    fn traced(
+        addresses: Option<RangeInclusive<u16>>,
+        cycles: Option<RangeInclusive<u64>>,
+    ) -> Vec<(u64, u16)> {
+        let output = SharedOutput::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()));
+        tracer.addresses = addresses;
+        tracer.cycles = cycles;
+        let mut cpu = Cpu::new();
+        cpu.memory_mut()
+            .write_data(0x200, &[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x00])
+            .unwrap();
+        cpu.set_tracer(Some(tracer));
+        for _ in 0..10 {
+            cpu.emulate_cycle().unwrap();
+        }
+        cpu.take_tracer().unwrap().finish().unwrap();
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        text.lines()
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let cycle = fields.next().unwrap().parse().unwrap();
+                let pc = u16::from_str_radix(fields.next().unwrap(), 16).unwrap();
+                (cycle, pc)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_instruction_is_traced_without_filters() {
+        let lines = traced(None, None);
+        assert_eq!(lines.len(), 10);
+        assert_eq!(
+            lines[..5],
+            [(0, 0x200), (1, 0x202), (2, 0x204), (3, 0x206), (4, 0x200)]
+        );
+    }
+
+    #[test]
+    fn filters_narrow_down_the_trace() {
+        assert_eq!(
+            traced(Some(0x202..=0x204), None),
+            [(1, 0x202), (2, 0x204), (5, 0x202), (6, 0x204), (9, 0x202)]
+        );
+        assert_eq!(traced(None, Some(3..=4)), [(3, 0x206), (4, 0x200)]);
+        assert_eq!(
+            traced(Some(0x202..=0x204), Some(2..=5)),
+            [(2, 0x204), (5, 0x202)]
+        );
+    }
+
+    #[test]
+    fn write_errors_come_out_of_finish() {
+        let mut tracer = Tracer::new(Box::new(BrokenOutput));
+        let cpu = Cpu::new();
+        tracer.trace(&cpu);
+        tracer.trace(&cpu);
+        assert_eq!(tracer.finish().unwrap_err().to_string(), "disk full");
+    }
+
+    #[test]
+    fn ranges_can_leave_either_end_open() {
+        assert_eq!(parse_range("10-20"), Ok(10..=20));
+        assert_eq!(parse_range("0x10-0x20"), Ok(16..=32));
+        assert_eq!(parse_range("-20"), Ok(0..=20));
+        assert_eq!(parse_range("10-"), Ok(10..=u64::MAX));
+        assert_eq!(parse_range("-"), Ok(0..=u64::MAX));
+        assert_eq!(parse_range("7-7"), Ok(7..=7));
+    }
+
+    #[test]
+    fn bad_ranges_are_rejected() {
+        assert_eq!(
+            parse_range("20-10"),
+            Err("'20-10' ends before it starts".to_string())
+        );
+        assert_eq!(
+            parse_range("10"),
+            Err("'10' should be <start>-<end>".to_string())
+        );
+        assert!(parse_range("a-b").is_err());
+        assert!(parse_range("10-20-30").is_err());
+    }
+
+    #[test]
+    fn address_ranges_stop_at_the_end_of_memory() {
+        assert_eq!(parse_address_range("0x200-0x2FF"), Ok(0x200..=0x2FF));
+        assert_eq!(parse_address_range("0x200-"), Ok(0x200..=0xFFFF));
+        assert_eq!(parse_address_range("0x200-0x12345"), Ok(0x200..=0xFFFF));
+        assert_eq!(
+            parse_address_range("0x10000-"),
+            Err("'0x10000-' is not an address range".to_string())
+        );
+        assert!(parse_address_range("0x300-0x200").is_err());
+    }
+}
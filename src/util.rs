@@ -0,0 +1,17 @@
+/// Parses a decimal number, or a hex one starting with `0x`, as used by every command line
+pub fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' is not a number", value))
+}
+
+/// Parses a number from 1 to `max`
+pub fn parse_count(value: &str, max: u64) -> Result<u32, String> {
+    let count = parse_number(value)?;
+    if count == 0 || count > max {
+        return Err(format!("'{}' should be from 1 to {}", value, max));
+    }
+    Ok(count as u32)
+}
@@ -0,0 +1,53 @@
+use chip8_emu::opcode::Opcode;
+
+#[test]
+fn every_known_instruction_round_trips() {
+    for word in 0..=0xFFFF_u16 {
+        let opcode = Opcode::from(word);
+        if let Opcode::Unknown { .. } = opcode {
+            continue;
+        }
+        assert_eq!(
+            u16::from(opcode),
+            word,
+            "{:#06X} decodes to {:?}",
+            word,
+            opcode
+        );
+    }
+}
+
+#[test]
+fn unknown_instructions_keep_their_word() {
+    for word in 0..=0xFFFF_u16 {
+        let opcode = Opcode::from(word);
+        if let Opcode::Unknown { opcode: raw } = opcode {
+            assert_eq!(raw, word);
+            assert_eq!(u16::from(opcode), word);
+        }
+    }
+}
+
+#[test]
+fn encoding_then_decoding_gives_the_same_opcode() {
+    let opcodes = [
+        Opcode::ClearScreen,
+        Opcode::SetILong,
+        Opcode::LoadAudioPattern,
+        Opcode::ScrollDown { lines: 15 },
+        Opcode::Draw {
+            register1: 0xA,
+            register2: 0xB,
+            height: 0,
+        },
+        Opcode::SaveRegisterRange {
+            register1: 3,
+            register2: 1,
+        },
+        Opcode::SelectPlanes { planes: 3 },
+        Opcode::LoadFlagsUntil { register: 7 },
+    ];
+    for &opcode in opcodes.iter() {
+        assert_eq!(Opcode::from(u16::from(opcode)), opcode);
+    }
+}